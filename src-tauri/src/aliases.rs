@@ -0,0 +1,383 @@
+// Local names for handles and "people" grouping several handles.
+//
+// Handles are matched by handle_key, so "+1 (555) 123-4567" and "5551234567"
+// share one alias. A handle's own display name wins over the name of the
+// person it is linked to. Overrides replace sender_name on returned messages
+// (the raw handle stays in sender_handle), and contact filters expand a person
+// to all of its handles.
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{app_db, handle_key, text, AppError, ContactIdentifier, Message};
+
+#[derive(Serialize, Debug)]
+pub struct Person {
+    id: i64,
+    name: String,
+    handles: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HandleAlias {
+    handle: String,
+    display_name: Option<String>,
+    person_id: Option<i64>,
+}
+
+// Snapshot of all aliases, loaded once per command
+#[derive(Default)]
+pub struct Aliases {
+    // Effective display name by handle key
+    names: HashMap<String, String>,
+    // Person id by handle key
+    person_of: HashMap<String, i64>,
+    handles_of: HashMap<i64, Vec<String>>,
+    person_names: HashMap<i64, String>,
+}
+
+impl Aliases {
+    pub fn load_from(conn: &Connection) -> Result<Self, AppError> {
+        let mut aliases = Aliases::default();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT a.handle_key, a.handle, a.person_id, a.display_name, p.name
+            FROM handle_alias a
+            LEFT JOIN person p ON p.id = a.person_id
+            "#,
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?;
+        for row in rows {
+            let (key, handle, person_id, display_name, person_name) = row?;
+            if let Some(name) = display_name.or_else(|| person_name.clone()) {
+                aliases.names.insert(key.clone(), name);
+            }
+            if let Some(person_id) = person_id {
+                aliases.person_of.insert(key, person_id);
+                aliases.handles_of.entry(person_id).or_default().push(handle);
+                if let Some(name) = person_name {
+                    aliases.person_names.insert(person_id, name);
+                }
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// Loads the current aliases. Errors are logged and give an empty set,
+    /// so an unreadable app database never hides messages.
+    pub fn load() -> Self {
+        match app_db::open_app_db().and_then(|conn| Self::load_from(&conn)) {
+            Ok(aliases) => aliases,
+            Err(e) => {
+                log::warn!("Failed to load handle aliases: {}", e);
+                Aliases::default()
+            }
+        }
+    }
+
+    pub fn display_name(&self, handle: &str) -> Option<&str> {
+        self.names.get(&handle_key(handle)).map(String::as_str)
+    }
+
+    /// Name of the person a handle is linked to, if any.
+    pub fn person_name(&self, handle: &str) -> Option<&str> {
+        let person_id = self.person_of.get(&handle_key(handle))?;
+        self.person_names.get(person_id).map(String::as_str)
+    }
+
+    /// Handle keys whose local name, or whose person's name, contains
+    /// `folded_name`, which must already be folded.
+    pub fn handle_keys_named(&self, folded_name: &str) -> Vec<String> {
+        if folded_name.is_empty() {
+            return Vec::new();
+        }
+        let matches = |name: &str| text::fold_text(name).contains(folded_name);
+        let mut keys: Vec<String> = self
+            .names
+            .iter()
+            .filter(|(_, name)| matches(name))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for (person_id, name) in &self.person_names {
+            if matches(name) {
+                keys.extend(self.handles_of.get(person_id).into_iter().flatten().map(|h| handle_key(h)));
+            }
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    /// Handle keys of every person with a handle whose key satisfies `matches`.
+    pub fn linked_keys_where(&self, matches: impl Fn(&str) -> bool) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .handles_of
+            .values()
+            .filter(|handles| handles.iter().any(|h| matches(&handle_key(h))))
+            .flatten()
+            .map(|h| handle_key(h))
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    /// Replaces sender_name with the local name for the sender's handle, if any.
+    pub fn apply(&self, message: &mut Message) {
+        if let Some(name) = message.sender_handle.as_deref().and_then(|h| self.display_name(h)) {
+            message.sender_name = Some(name.to_string());
+        }
+        for context in message.context_before.iter_mut().chain(message.context_after.iter_mut()) {
+            self.apply(context);
+        }
+    }
+
+    /// Key identifying who is behind a handle: the linked person if any,
+    /// otherwise the handle itself.
+    pub fn identity_key(&self, handle: &str) -> String {
+        let key = handle_key(handle);
+        match self.person_of.get(&key) {
+            Some(person_id) => format!("person:{}", person_id),
+            None => key,
+        }
+    }
+
+    // All handles linked to the same person as `handle`, including itself
+    fn linked_handles(&self, handle: &str) -> Vec<String> {
+        match self.person_of.get(&handle_key(handle)) {
+            Some(person_id) => self.handles_of.get(person_id).cloned().unwrap_or_default(),
+            None => vec![handle.to_string()],
+        }
+    }
+
+    /// Adds every handle of a linked person to each identifier, and resolves
+    /// identifiers that name a person directly.
+    pub fn expand(&self, identifier: &ContactIdentifier) -> ContactIdentifier {
+        let mut handles: Vec<String> = identifier
+            .contact_id
+            .iter()
+            .chain(&identifier.phones)
+            .chain(&identifier.emails)
+            .flat_map(|h| self.linked_handles(h))
+            .collect();
+        if let Some(person_id) = identifier.person_id {
+            handles.extend(self.handles_of.get(&person_id).cloned().unwrap_or_default());
+        }
+
+        let mut expanded = ContactIdentifier {
+            contact_id: identifier.contact_id.clone(),
+            phones: Vec::new(),
+            emails: Vec::new(),
+            person_id: identifier.person_id,
+        };
+        for handle in handles {
+            let list = if handle.contains('@') { &mut expanded.emails } else { &mut expanded.phones };
+            if !list.iter().any(|h| handle_key(h) == handle_key(&handle)) {
+                list.push(handle);
+            }
+        }
+        expanded
+    }
+}
+
+fn load_person(conn: &Connection, id: i64) -> Result<Person, AppError> {
+    let name: String = conn
+        .query_row("SELECT name FROM person WHERE id = ?", [id], |row| row.get(0))
+        .optional()?
+        .ok_or_else(|| AppError::OtherError(format!("Person {} not found", id)))?;
+    let mut stmt = conn.prepare("SELECT handle FROM handle_alias WHERE person_id = ? ORDER BY handle")?;
+    let handles = stmt
+        .query_map([id], |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(Person { id, name, handles })
+}
+
+// Points `handles` at `person_id` and unlinks any other handles of that person
+fn link_handles(conn: &Connection, person_id: i64, handles: &[String]) -> Result<(), AppError> {
+    conn.execute("UPDATE handle_alias SET person_id = NULL WHERE person_id = ?", [person_id])?;
+    for handle in handles.iter().map(|h| h.trim()).filter(|h| !h.is_empty()) {
+        conn.execute(
+            r#"
+            INSERT INTO handle_alias (handle_key, handle, person_id) VALUES (?1, ?2, ?3)
+            ON CONFLICT(handle_key) DO UPDATE SET handle = excluded.handle, person_id = excluded.person_id
+            "#,
+            rusqlite::params![handle_key(handle), handle, person_id],
+        )?;
+    }
+    conn.execute("DELETE FROM handle_alias WHERE person_id IS NULL AND display_name IS NULL", [])?;
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::OtherError("Name cannot be empty".to_string()));
+    }
+    Ok(name.to_string())
+}
+
+#[tauri::command]
+pub async fn list_people() -> Result<Vec<Person>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut stmt = conn.prepare("SELECT id FROM person ORDER BY name COLLATE NOCASE")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<i64>, _>>()?;
+    ids.into_iter().map(|id| load_person(&conn, id)).collect()
+}
+
+#[tauri::command]
+pub async fn create_person(name: String, handles: Vec<String>) -> Result<Person, AppError> {
+    let name = validate_name(&name)?;
+    let mut conn = app_db::open_app_db()?;
+    let tx = conn.transaction()?;
+    let now = app_db::now();
+    tx.execute(
+        "INSERT INTO person (name, created_at, updated_at) VALUES (?, ?, ?)",
+        rusqlite::params![name, now, now],
+    )?;
+    let id = tx.last_insert_rowid();
+    link_handles(&tx, id, &handles)?;
+    tx.commit()?;
+    load_person(&conn, id)
+}
+
+#[tauri::command]
+pub async fn update_person(id: i64, name: String, handles: Vec<String>) -> Result<Person, AppError> {
+    let name = validate_name(&name)?;
+    let mut conn = app_db::open_app_db()?;
+    let tx = conn.transaction()?;
+    let updated = tx.execute(
+        "UPDATE person SET name = ?, updated_at = ? WHERE id = ?",
+        rusqlite::params![name, app_db::now(), id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::OtherError(format!("Person {} not found", id)));
+    }
+    link_handles(&tx, id, &handles)?;
+    tx.commit()?;
+    load_person(&conn, id)
+}
+
+#[tauri::command]
+pub async fn delete_person(id: i64) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    conn.execute("DELETE FROM person WHERE id = ?", [id])?;
+    conn.execute("DELETE FROM handle_alias WHERE person_id IS NULL AND display_name IS NULL", [])?;
+    Ok(())
+}
+
+/// Sets or, with no name, clears the local display name of a handle.
+#[tauri::command]
+pub async fn set_handle_name(handle: String, display_name: Option<String>) -> Result<(), AppError> {
+    let handle = handle.trim().to_string();
+    if handle.is_empty() {
+        return Err(AppError::OtherError("Handle cannot be empty".to_string()));
+    }
+    let display_name = display_name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    let conn = app_db::open_app_db()?;
+    conn.execute(
+        r#"
+        INSERT INTO handle_alias (handle_key, handle, display_name) VALUES (?1, ?2, ?3)
+        ON CONFLICT(handle_key) DO UPDATE SET display_name = excluded.display_name
+        "#,
+        rusqlite::params![handle_key(&handle), handle, display_name],
+    )?;
+    conn.execute("DELETE FROM handle_alias WHERE person_id IS NULL AND display_name IS NULL", [])?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_handle_aliases() -> Result<Vec<HandleAlias>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut stmt = conn.prepare("SELECT handle, display_name, person_id FROM handle_alias ORDER BY handle")?;
+    let aliases = stmt
+        .query_map([], |row| {
+            Ok(HandleAlias {
+                handle: row.get(0)?,
+                display_name: row.get(1)?,
+                person_id: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ann has a phone number and an email; the email also has its own name
+    fn test_aliases() -> Aliases {
+        let conn = app_db::open_test_db();
+        conn.execute("INSERT INTO person (id, name, created_at, updated_at) VALUES (1, 'Ann Lee', 0, 0)", [])
+            .unwrap();
+        link_handles(&conn, 1, &["+1 (555) 123-4567".to_string(), "Ann@Example.com".to_string()]).unwrap();
+        conn.execute("UPDATE handle_alias SET display_name = 'Annie' WHERE handle_key = 'ann@example.com'", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO handle_alias (handle_key, handle, display_name) VALUES ('5559876543', '5559876543', 'Plumber')",
+            [],
+        )
+        .unwrap();
+        Aliases::load_from(&conn).unwrap()
+    }
+
+    #[test]
+    fn handle_name_wins_over_person_name() {
+        let aliases = test_aliases();
+        assert_eq!(aliases.display_name("5551234567"), Some("Ann Lee"));
+        assert_eq!(aliases.display_name("ann@example.com"), Some("Annie"));
+        assert_eq!(aliases.person_name("ann@example.com"), Some("Ann Lee"));
+        assert_eq!(aliases.person_name("5559876543"), None);
+    }
+
+    #[test]
+    fn identity_groups_linked_handles() {
+        let aliases = test_aliases();
+        assert_eq!(aliases.identity_key("+15551234567"), aliases.identity_key("ANN@example.com"));
+        assert_eq!(aliases.identity_key("+1 555 987 6543"), "5559876543");
+    }
+
+    #[test]
+    fn names_find_every_linked_handle() {
+        let aliases = test_aliases();
+        assert_eq!(aliases.handle_keys_named("lee"), vec!["5551234567", "ann@example.com"]);
+        assert_eq!(aliases.handle_keys_named("annie"), vec!["ann@example.com"]);
+        assert_eq!(aliases.handle_keys_named("plumb"), vec!["5559876543"]);
+        assert!(aliases.handle_keys_named("").is_empty());
+    }
+
+    #[test]
+    fn linked_keys_expand_to_the_person() {
+        let aliases = test_aliases();
+        assert_eq!(
+            aliases.linked_keys_where(|key| key.ends_with("1234567")),
+            vec!["5551234567", "ann@example.com"]
+        );
+        // Handles that aren't linked to a person are left to the caller
+        assert!(aliases.linked_keys_where(|key| key == "5559876543").is_empty());
+    }
+
+    #[test]
+    fn expand_adds_linked_handles() {
+        let aliases = test_aliases();
+        let identifier = ContactIdentifier {
+            contact_id: None,
+            phones: vec!["555-123-4567".to_string()],
+            emails: Vec::new(),
+            person_id: None,
+        };
+        let expanded = aliases.expand(&identifier);
+        assert_eq!(expanded.phones, vec!["+1 (555) 123-4567"]);
+        assert_eq!(expanded.emails, vec!["Ann@Example.com"]);
+    }
+}
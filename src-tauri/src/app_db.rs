@@ -0,0 +1,197 @@
+// Database owned by the app, for state that does not belong in chat.db.
+//
+// chat.db is opened read-only in spirit and may be replaced by Messages at any
+// time, so anything the user creates in this app lives in its own SQLite file
+// under Application Support. Tables are created on first open.
+use rusqlite::{Connection, OptionalExtension};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::AppError;
+
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS saved_search (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        params TEXT NOT NULL,
+        date_range TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        last_run_at INTEGER,
+        last_seen_message_id INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS search_history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        query TEXT NOT NULL,
+        params TEXT NOT NULL,
+        searched_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS search_history_searched_at ON search_history (searched_at);
+
+    CREATE TABLE IF NOT EXISTS reminder (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        message_guid TEXT NOT NULL UNIQUE,
+        due_at INTEGER NOT NULL,
+        note TEXT,
+        created_at INTEGER NOT NULL,
+        notified_at INTEGER,
+        completed_at INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS reminder_due_at ON reminder (due_at);
+
+    CREATE TABLE IF NOT EXISTS bookmark (
+        message_guid TEXT PRIMARY KEY,
+        starred INTEGER NOT NULL DEFAULT 0,
+        note TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS bookmark_tag (
+        message_guid TEXT NOT NULL REFERENCES bookmark (message_guid) ON DELETE CASCADE,
+        tag TEXT NOT NULL COLLATE NOCASE,
+        PRIMARY KEY (message_guid, tag)
+    );
+    CREATE INDEX IF NOT EXISTS bookmark_tag_tag ON bookmark_tag (tag);
+
+    CREATE TABLE IF NOT EXISTS chat_meta (
+        chat_guid TEXT PRIMARY KEY,
+        pinned INTEGER NOT NULL DEFAULT 0,
+        hidden INTEGER NOT NULL DEFAULT 0,
+        color TEXT,
+        updated_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS chat_label (
+        chat_guid TEXT NOT NULL,
+        label TEXT NOT NULL COLLATE NOCASE,
+        PRIMARY KEY (chat_guid, label)
+    );
+    CREATE INDEX IF NOT EXISTS chat_label_label ON chat_label (label);
+
+    CREATE TABLE IF NOT EXISTS person (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS handle_alias (
+        handle_key TEXT PRIMARY KEY,
+        handle TEXT NOT NULL,
+        display_name TEXT,
+        person_id INTEGER REFERENCES person (id) ON DELETE SET NULL
+    );
+    CREATE INDEX IF NOT EXISTS handle_alias_person ON handle_alias (person_id);
+
+    CREATE TABLE IF NOT EXISTS setting (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+"#;
+
+pub fn get_app_db_path() -> Result<PathBuf, AppError> {
+    let home = dirs::home_dir().ok_or(AppError::OtherError("Home directory not found".to_string()))?;
+    Ok(home.join("Library/Application Support/iMessage Search/app.db"))
+}
+
+/// Opens the app database, creating the file and its tables if needed.
+pub fn open_app_db() -> Result<Connection, AppError> {
+    // The schema is set up by the first successful open only, since messages
+    // and search results open the database for every batch
+    static SCHEMA_READY: AtomicBool = AtomicBool::new(false);
+
+    let path = get_app_db_path()?;
+    let ready = SCHEMA_READY.load(Ordering::Acquire);
+    if !ready {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    let conn = Connection::open(&path).map_err(AppError::DatabaseConnectionError)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON")?;
+    if !ready {
+        conn.execute_batch(SCHEMA)?;
+        SCHEMA_READY.store(true, Ordering::Release);
+    }
+    Ok(conn)
+}
+
+/// An empty in-memory app database for tests.
+#[cfg(test)]
+pub fn open_test_db() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+    conn.execute_batch(SCHEMA).unwrap();
+    conn
+}
+
+// Managed Tauri state holding one connection for commands that run on every
+// keystroke, so they don't reopen the file and rerun the schema each time
+#[derive(Default)]
+pub struct AppDbState(Mutex<Option<Connection>>);
+
+impl AppDbState {
+    /// Runs `f` on the shared connection, opening it on first use.
+    pub fn with<T>(&self, f: impl FnOnce(&Connection) -> Result<T, AppError>) -> Result<T, AppError> {
+        let mut slot = self.0.lock().map_err(|e| AppError::OtherError(e.to_string()))?;
+        let conn = match slot.take() {
+            Some(conn) => conn,
+            None => open_app_db()?,
+        };
+        let result = f(&conn);
+        *slot = Some(conn);
+        result
+    }
+}
+
+/// Attaches the app database read-only to a chat.db connection as `app`, so
+/// searches can join against the user's annotations. Safe to call repeatedly.
+pub fn attach(conn: &Connection) -> Result<(), AppError> {
+    let attached: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_database_list WHERE name = 'app'",
+        [],
+        |row| row.get(0),
+    )?;
+    if attached > 0 {
+        return Ok(());
+    }
+
+    // Opening once creates the file and tables, which a read-only attach can't
+    open_app_db()?;
+    let path = get_app_db_path()?.to_string_lossy().to_string();
+    let uri = format!(
+        "file:{}?mode=ro",
+        path.replace('%', "%25").replace(' ', "%20").replace('?', "%3f").replace('#', "%23")
+    );
+    conn.execute("ATTACH DATABASE ? AS app", [uri])?;
+    Ok(())
+}
+
+/// Reads a value from the setting table.
+pub fn get_setting(conn: &Connection, key: &str) -> Result<Option<String>, AppError> {
+    Ok(conn
+        .query_row("SELECT value FROM setting WHERE key = ?", [key], |row| row.get(0))
+        .optional()?)
+}
+
+pub fn set_setting(conn: &Connection, key: &str, value: &str) -> Result<(), AppError> {
+    conn.execute(
+        "INSERT INTO setting (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        [key, value],
+    )?;
+    Ok(())
+}
+
+pub fn delete_setting(conn: &Connection, key: &str) -> Result<(), AppError> {
+    conn.execute("DELETE FROM setting WHERE key = ?", [key])?;
+    Ok(())
+}
+
+/// Current time as Unix seconds, used for the app's own timestamps.
+pub fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
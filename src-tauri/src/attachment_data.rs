@@ -0,0 +1,326 @@
+// Structured data read from shared contact cards, locations and calendar files.
+//
+// Messages sends a contact as a .vcf vCard, a shared location as a .loc.vcf
+// vCard whose URL points at Apple Maps, and an invite as an .ics file. The
+// files are small text files, parsed when messages are returned and exposed
+// on the message as `attachment_data`.
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+
+// Largest attachment file read for parsing
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+// Conditions on message m for the has: operators
+const LOCATION_ATTACHMENT: &str = "(a.filename LIKE '%.loc.vcf' OR a.mime_type = 'text/x-vlocation')";
+const CONTACT_ATTACHMENT: &str = "((a.mime_type IN ('text/vcard', 'text/x-vcard') OR a.filename LIKE '%.vcf') AND a.filename NOT LIKE '%.loc.vcf')";
+const EVENT_ATTACHMENT: &str = "(a.mime_type = 'text/calendar' OR a.filename LIKE '%.ics')";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AttachmentData {
+    Contact {
+        name: Option<String>,
+        phones: Vec<String>,
+        emails: Vec<String>,
+        organization: Option<String>,
+    },
+    Location {
+        name: Option<String>,
+        latitude: Option<f64>,
+        longitude: Option<f64>,
+        address: Option<String>,
+    },
+    Event {
+        title: Option<String>,
+        start: Option<i64>,          // Unix seconds
+        end: Option<i64>,
+        all_day: bool,
+        location: Option<String>,
+    },
+}
+
+/// Condition for has:location, has:contact or has:event, if `kind` is one of them.
+pub fn has_sql(kind: &str) -> Option<String> {
+    let condition = match kind {
+        "location" => LOCATION_ATTACHMENT,
+        "contact" => CONTACT_ATTACHMENT,
+        "event" => EVENT_ATTACHMENT,
+        _ => return None,
+    };
+    Some(format!(
+        r#"EXISTS (
+            SELECT 1
+            FROM attachment a
+            JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID
+            WHERE maj.message_id = m.ROWID AND {}
+        )"#,
+        condition
+    ))
+}
+
+// One content line: property name without group, parameters, value
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+// Parses vCard and iCalendar content lines, joining folded lines first
+fn content_lines(text: &str) -> Vec<Property> {
+    let mut unfolded: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), unfolded.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => unfolded.push(line.to_string()),
+        }
+    }
+
+    unfolded
+        .iter()
+        .filter_map(|line| {
+            let (head, value) = line.split_once(':')?;
+            let mut parts = head.split(';');
+            let name = parts.next()?;
+            // Drop a group prefix such as "item1."
+            let name = name.rsplit('.').next().unwrap_or(name).to_uppercase();
+            let params = parts
+                .map(|p| match p.split_once('=') {
+                    Some((k, v)) => (k.to_string(), v.trim_matches('"').to_string()),
+                    None => ("TYPE".to_string(), p.to_string()),
+                })
+                .collect();
+            Some(Property { name, params, value: value.to_string() })
+        })
+        .collect()
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => out.push('\n'),
+                Some(other) => out.push(other),
+                None => {},
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out.trim().to_string()
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() { None } else { Some(value) }
+}
+
+// "street;city;..." ADR components as one line, skipping empty parts
+fn format_address(value: &str) -> Option<String> {
+    let parts: Vec<String> = value.split(';').map(unescape).filter(|p| !p.is_empty()).collect();
+    non_empty(parts.join(", "))
+}
+
+// Coordinates from an Apple Maps URL, in its ll= or q= parameter
+fn coordinates(map_url: &str) -> Option<(f64, f64)> {
+    let url = url::Url::parse(map_url).ok()?;
+    url.query_pairs()
+        .filter(|(key, _)| key == "ll" || key == "q" || key == "sll")
+        .find_map(|(_, value)| {
+            let (lat, lon) = value.split_once(',')?;
+            Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?))
+        })
+}
+
+fn parse_vcard(text: &str, is_location: bool) -> Option<AttachmentData> {
+    let properties = content_lines(text);
+    let value = |name: &str| properties.iter().find(|p| p.name == name).map(|p| unescape(&p.value)).and_then(non_empty);
+    let name = value("FN").or_else(|| {
+        let n = properties.iter().find(|p| p.name == "N")?;
+        let parts: Vec<String> = n.value.split(';').take(2).map(unescape).filter(|p| !p.is_empty()).collect();
+        // N is "family;given"
+        non_empty(parts.into_iter().rev().collect::<Vec<_>>().join(" "))
+    });
+    let address = properties.iter().find(|p| p.name == "ADR").and_then(|p| format_address(&p.value));
+
+    if is_location {
+        let (latitude, longitude) = properties
+            .iter()
+            .filter(|p| p.name == "URL")
+            .find_map(|p| coordinates(&unescape(&p.value)))
+            .map_or((None, None), |(lat, lon)| (Some(lat), Some(lon)));
+        return Some(AttachmentData::Location { name, latitude, longitude, address });
+    }
+
+    let all = |name: &str| properties.iter().filter(|p| p.name == name).map(|p| unescape(&p.value)).filter(|v| !v.is_empty()).collect();
+    Some(AttachmentData::Contact {
+        name,
+        phones: all("TEL"),
+        emails: all("EMAIL"),
+        organization: value("ORG").map(|org| org.trim_matches(';').replace(';', ", ")),
+    })
+}
+
+// ICS date or date-time as Unix seconds, and whether it was a whole day.
+// Times without a UTC "Z" are taken as local time.
+fn parse_ics_time(property: &Property) -> Option<(i64, bool)> {
+    let value = property.value.trim();
+    if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+        let date = chrono::NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        let local = chrono::Local.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest()?;
+        return Some((local.timestamp(), true));
+    }
+    let naive = chrono::NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S").ok()?;
+    let timestamp = if value.ends_with('Z') {
+        naive.and_utc().timestamp()
+    } else {
+        chrono::Local.from_local_datetime(&naive).earliest()?.timestamp()
+    };
+    Some((timestamp, false))
+}
+
+fn parse_ics(text: &str) -> Option<AttachmentData> {
+    let properties = content_lines(text);
+    // Only the first event's properties
+    let start = properties.iter().position(|p| p.name == "BEGIN" && p.value.eq_ignore_ascii_case("VEVENT"))?;
+    let event: Vec<&Property> = properties[start + 1..]
+        .iter()
+        .take_while(|p| !(p.name == "END" && p.value.eq_ignore_ascii_case("VEVENT")))
+        .collect();
+    let find = |name: &str| event.iter().find(|p| p.name == name).copied();
+
+    let start = find("DTSTART").and_then(parse_ics_time);
+    Some(AttachmentData::Event {
+        title: find("SUMMARY").map(|p| unescape(&p.value)).and_then(non_empty),
+        start: start.map(|(time, _)| time),
+        end: find("DTEND").and_then(parse_ics_time).map(|(time, _)| time),
+        all_day: start.is_some_and(|(_, all_day)| all_day),
+        location: find("LOCATION").map(|p| unescape(&p.value)).and_then(non_empty),
+    })
+}
+
+/// Parses the attachment at `path` if it is a contact card, location or invite.
+pub fn parse(path: &str, mime_type: Option<&str>) -> Option<AttachmentData> {
+    let lower = path.to_lowercase();
+    let mime_type = mime_type.unwrap_or("");
+    let is_location = lower.ends_with(".loc.vcf") || mime_type == "text/x-vlocation";
+    let is_vcard = is_location || lower.ends_with(".vcf") || mime_type == "text/vcard" || mime_type == "text/x-vcard";
+    let is_ics = lower.ends_with(".ics") || mime_type == "text/calendar";
+    if !is_vcard && !is_ics {
+        return None;
+    }
+
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()?.join(rest),
+        None => std::path::PathBuf::from(path),
+    };
+    if std::fs::metadata(&path).ok()?.len() > MAX_FILE_BYTES {
+        return None;
+    }
+    let text = match std::fs::read(&path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            log::warn!("Could not read attachment {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    if is_ics {
+        parse_ics(&text)
+    } else {
+        parse_vcard(&text, is_location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_contact_cards() {
+        let card = "BEGIN:VCARD\r\nVERSION:3.0\r\nN:Doe;Jane;;;\r\nFN:Jane Doe\r\nORG:Acme\\, Inc.;Sales;\r\n\
+                    item1.TEL;type=CELL;type=pref:+1 (555) 123-4567\r\nTEL;type=WORK:\r\n\
+                    EMAIL;type=INTERNET:jane@example.com\r\nEND:VCARD\r\n";
+        assert_eq!(
+            parse_vcard(card, false),
+            Some(AttachmentData::Contact {
+                name: Some("Jane Doe".to_string()),
+                phones: vec!["+1 (555) 123-4567".to_string()],
+                emails: vec!["jane@example.com".to_string()],
+                organization: Some("Acme, Inc., Sales".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn contact_name_falls_back_to_n() {
+        let card = "BEGIN:VCARD\nN:Doe;Jane;;;\nEND:VCARD\n";
+        let Some(AttachmentData::Contact { name, .. }) = parse_vcard(card, false) else { panic!() };
+        assert_eq!(name.as_deref(), Some("Jane Doe"));
+    }
+
+    #[test]
+    fn parses_shared_locations() {
+        let card = "BEGIN:VCARD\nVERSION:3.0\nFN:Dropped Pin\nADR;type=HOME:;;1 Infinite Loop;Cupertino;CA;95014;\n\
+                    item1.URL;type=pref:http://maps.apple.com/?ll=37.331\\,-122.030&q=Dropped%20Pin\n\
+                    END:VCARD\n";
+        assert_eq!(
+            parse_vcard(card, true),
+            Some(AttachmentData::Location {
+                name: Some("Dropped Pin".to_string()),
+                latitude: Some(37.331),
+                longitude: Some(-122.030),
+                address: Some("1 Infinite Loop, Cupertino, CA, 95014".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn unfolds_continued_lines() {
+        let card = "BEGIN:VCARD\nFN:Jane\n  Doe\nEMAIL:jane@\n\texample.com\nEND:VCARD\n";
+        let Some(AttachmentData::Contact { name, emails, .. }) = parse_vcard(card, false) else { panic!() };
+        assert_eq!(name.as_deref(), Some("Jane Doe"));
+        assert_eq!(emails, vec!["jane@example.com"]);
+    }
+
+    #[test]
+    fn parses_the_first_event() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Dinner\\, at 7\r\nDTSTART:20240301T190000Z\r\n\
+                   DTEND:20240301T210000Z\r\nLOCATION:Cafe\\nMain St\r\nEND:VEVENT\r\n\
+                   BEGIN:VEVENT\r\nSUMMARY:Second\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        assert_eq!(
+            parse_ics(ics),
+            Some(AttachmentData::Event {
+                title: Some("Dinner, at 7".to_string()),
+                start: Some(1709319600),
+                end: Some(1709326800),
+                all_day: false,
+                location: Some("Cafe\nMain St".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn all_day_events_start_at_local_midnight() {
+        let ics = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20240301\nEND:VEVENT\n";
+        let midnight = chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let expected = chrono::Local.from_local_datetime(&midnight).earliest().unwrap().timestamp();
+        let Some(AttachmentData::Event { start, all_day, title, .. }) = parse_ics(ics) else { panic!() };
+        assert_eq!(start, Some(expected));
+        assert!(all_day);
+        assert_eq!(title, None);
+    }
+
+    #[test]
+    fn files_without_events_are_not_invites() {
+        assert_eq!(parse_ics("BEGIN:VCALENDAR\nEND:VCALENDAR\n"), None);
+        assert_eq!(has_sql("photo"), None);
+        assert!(has_sql("event").unwrap().contains(EVENT_ATTACHMENT));
+    }
+}
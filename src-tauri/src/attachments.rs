@@ -0,0 +1,482 @@
+// Attachment files: resolving chat.db paths and serving them to the webview.
+//
+// attachment.filename is stored as "~/Library/Messages/Attachments/..". The
+// part below Attachments is resolved against a configurable root, so a copied
+// or backed-up Attachments folder can be used, and checked on disk. Files are
+// served over the attachment:// scheme, which only reads below that root and
+// answers Range requests so video and audio can seek without loading the
+// whole file. The root is read from the app database once and kept in memory.
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::UriSchemeResponder;
+
+use crate::{app_db, thumbnails, AppError, Message};
+
+pub const SCHEME: &str = "attachment";
+
+const ROOT_SETTING: &str = "attachments_root";
+
+// Where Messages keeps attachments, relative to the home directory
+const MESSAGES_ATTACHMENTS_DIR: &str = "Library/Messages/Attachments";
+
+// Most bytes returned for one Range request; the webview asks for the rest
+// as playback reaches it
+const MAX_RANGE_BYTES: u64 = 8 * 1024 * 1024;
+
+// Characters escaped in a path segment of an attachment:// URL
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'?').add(b'<').add(b'>').add(b'`');
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentState {
+    Present,
+    Missing,
+    // Not on disk, but stored in iCloud and downloadable from Messages
+    Offloaded,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AttachmentInfo {
+    id: i64,
+    guid: String,
+    transfer_name: Option<String>,
+    mime_type: Option<String>,
+    total_bytes: i64,
+    is_sticker: bool,
+    path: Option<String>,
+    // Only for files below the attachments root
+    url: Option<String>,
+    // Only for images on disk
+    thumbnail_url: Option<String>,
+    state: AttachmentState,
+}
+
+fn default_root() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(MESSAGES_ATTACHMENTS_DIR))
+}
+
+// Root in effect, None until first read from the app database
+static ROOT: RwLock<Option<Option<PathBuf>>> = RwLock::new(None);
+
+// The configured root, or the Messages attachments folder
+fn configured_root() -> Option<PathBuf> {
+    if let Some(root) = ROOT.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return root.clone();
+    }
+    let configured = app_db::open_app_db()
+        .and_then(|conn| app_db::get_setting(&conn, ROOT_SETTING))
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to read attachments root: {}", e);
+            None
+        });
+    let root = configured.map(PathBuf::from).or_else(default_root);
+    *ROOT.write().unwrap_or_else(|e| e.into_inner()) = Some(root.clone());
+    root
+}
+
+// Path of an attachment below the Attachments folder, or None if it is elsewhere
+fn relative_path(filename: &str) -> Option<PathBuf> {
+    let marker = format!("/{}/", MESSAGES_ATTACHMENTS_DIR);
+    let start = filename.find(&marker)? + marker.len();
+    let relative = PathBuf::from(&filename[start..]);
+    // Only plain names, so a crafted filename can't climb out of the root
+    if relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        Some(relative)
+    } else {
+        None
+    }
+}
+
+fn url_for(relative: &Path) -> String {
+    let segments: Vec<String> = relative
+        .components()
+        .map(|c| utf8_percent_encode(&c.as_os_str().to_string_lossy(), SEGMENT).to_string())
+        .collect();
+    format!("{}://localhost/{}", SCHEME, segments.join("/"))
+}
+
+// Attachment filename resolved against the root
+pub struct Resolved {
+    pub path: Option<String>,
+    pub url: Option<String>,
+    pub exists: bool,
+}
+
+// Resolves attachment filenames against the root read once at creation
+pub struct Resolver {
+    root: Option<PathBuf>,
+}
+
+impl Resolver {
+    pub fn load() -> Self {
+        Resolver { root: configured_root() }
+    }
+
+    #[cfg(test)]
+    pub fn with_root(root: Option<PathBuf>) -> Self {
+        Resolver { root }
+    }
+
+    pub fn resolve(&self, filename: &str) -> Resolved {
+        let (path, url) = match (relative_path(filename), &self.root) {
+            (Some(relative), Some(root)) => (Some(root.join(&relative)), Some(url_for(&relative))),
+            // Outside the Attachments folder, e.g. the sticker cache
+            _ => match filename.strip_prefix("~/") {
+                Some(rest) => (dirs::home_dir().map(|home| home.join(rest)), None),
+                None => (Some(PathBuf::from(filename)), None),
+            },
+        };
+        let exists = path.as_ref().is_some_and(|p| p.is_file());
+        Resolved {
+            path: path.map(|p| p.to_string_lossy().to_string()),
+            url,
+            exists,
+        }
+    }
+}
+
+// Those of `message_ids` with an attachment stored in iCloud
+fn offloaded_message_ids(conn: &Connection, message_ids: &[i64]) -> Result<Vec<i64>, AppError> {
+    let ids: Vec<String> = message_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        r#"
+        SELECT maj.message_id
+        FROM message_attachment_join maj
+        JOIN attachment a ON maj.attachment_id = a.ROWID
+        WHERE maj.message_id IN ({}) AND COALESCE(a.ck_record_id, '') != ''
+        "#,
+        ids.join(", ")
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let offloaded = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<i64>, _>>()?;
+    Ok(offloaded)
+}
+
+/// Replaces attachment paths with resolved absolute paths and sets their URL and state.
+pub fn annotate(messages: &mut [Message]) {
+    let resolver = Resolver::load();
+    let mut missing: Vec<i64> = Vec::new();
+    for message in messages.iter_mut() {
+        let Some(filename) = &message.attachment_path else {
+            continue;
+        };
+        let resolved = resolver.resolve(filename);
+        if !resolved.exists {
+            missing.push(message.id);
+        }
+        message.attachment_path = resolved.path.or(message.attachment_path.take());
+        message.attachment_url = resolved.url;
+        message.attachment_state = Some(if resolved.exists { AttachmentState::Present } else { AttachmentState::Missing });
+    }
+    if missing.is_empty() {
+        return;
+    }
+
+    match crate::open_imessage_db().and_then(|conn| offloaded_message_ids(&conn, &missing)) {
+        Ok(offloaded) => {
+            for message in messages.iter_mut().filter(|m| offloaded.contains(&m.id)) {
+                message.attachment_state = Some(AttachmentState::Offloaded);
+            }
+        },
+        Err(e) => log::warn!("Could not check offloaded attachments: {}", e),
+    }
+}
+
+/// Every attachment of a message with its resolved path and state.
+#[tauri::command]
+pub async fn get_message_attachment_info(message_id: i64) -> Result<Vec<AttachmentInfo>, AppError> {
+    let conn = crate::open_imessage_db()?;
+    let mut stmt = conn.prepare(
+        r#"
+        SELECT
+            a.ROWID,
+            a.guid,
+            a.filename,
+            a.transfer_name,
+            a.mime_type,
+            COALESCE(a.total_bytes, 0),
+            COALESCE(a.is_sticker, 0),
+            COALESCE(a.ck_record_id, '') != ''
+        FROM
+            attachment a
+        JOIN
+            message_attachment_join maj ON maj.attachment_id = a.ROWID
+        WHERE
+            maj.message_id = ?
+        ORDER BY
+            a.ROWID
+        "#,
+    )?;
+
+    let resolver = Resolver::load();
+    let attachments = stmt
+        .query_map([message_id], |row| {
+            let filename: Option<String> = row.get(2)?;
+            let in_icloud: bool = row.get(7)?;
+            let resolved = filename.as_deref().map(|f| resolver.resolve(f));
+            let state = match &resolved {
+                Some(resolved) if resolved.exists => AttachmentState::Present,
+                _ if in_icloud => AttachmentState::Offloaded,
+                _ => AttachmentState::Missing,
+            };
+            let (path, url) = resolved.map_or((None, None), |r| (r.path, r.url));
+            let guid: String = row.get(1)?;
+            let mime_type: Option<String> = row.get(4)?;
+            let is_image = mime_type.as_deref().is_some_and(|m| m.starts_with("image/"));
+            Ok(AttachmentInfo {
+                id: row.get(0)?,
+                thumbnail_url: (is_image && state == AttachmentState::Present)
+                    .then(|| thumbnails::thumbnail_url(&guid, thumbnails::DEFAULT_SIZE)),
+                guid,
+                transfer_name: row.get(3)?,
+                mime_type,
+                total_bytes: row.get(5)?,
+                is_sticker: row.get(6)?,
+                path,
+                url,
+                state,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(attachments)
+}
+
+#[tauri::command]
+pub async fn get_attachments_root() -> Result<Option<String>, AppError> {
+    Ok(configured_root().map(|root| root.to_string_lossy().to_string()))
+}
+
+/// Sets the folder attachment paths are resolved against. None restores the default.
+#[tauri::command]
+pub async fn set_attachments_root(path: Option<String>) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    match path.map(|p| p.trim().to_string()).filter(|p| !p.is_empty()) {
+        Some(path) => {
+            if !Path::new(&path).is_dir() {
+                return Err(AppError::OtherError(format!("Not a folder: {}", path)));
+            }
+            app_db::set_setting(&conn, ROOT_SETTING, &path)?;
+        },
+        None => app_db::delete_setting(&conn, ROOT_SETTING)?,
+    }
+    // Read again on next use
+    *ROOT.write().unwrap_or_else(|e| e.into_inner()) = None;
+    Ok(())
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "heic" => "image/heic",
+        "webp" => "image/webp",
+        "tiff" | "tif" => "image/tiff",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "caf" => "audio/x-caf",
+        "amr" => "audio/amr",
+        "pdf" => "application/pdf",
+        "vcf" => "text/vcard",
+        "ics" => "text/calendar",
+        "txt" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn error_response(status: StatusCode) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(status.canonical_reason().unwrap_or("").as_bytes().to_vec())
+        .unwrap_or_default()
+}
+
+// A single "bytes=" range as inclusive offsets into a file of `len` bytes,
+// cut to MAX_RANGE_BYTES. None means the header is not one we handle, so the
+// whole file is sent; Some(None) means the range lies outside the file.
+fn parse_range(value: &str, len: u64) -> Option<Option<(u64, u64)>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return Some(None);
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        },
+        (start, "") => {
+            let start: u64 = start.parse().ok()?;
+            (start, u64::MAX)
+        },
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end)
+        },
+    };
+    let end = end.min(len.saturating_sub(1)).min(start.saturating_add(MAX_RANGE_BYTES - 1));
+    Some((start < len).then_some((start, end)))
+}
+
+// The file a request asks for, only if it is inside `root`
+fn requested_file(request: &Request<Vec<u8>>, root: &Path) -> Option<PathBuf> {
+    let relative: PathBuf = request
+        .uri()
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+    if relative.as_os_str().is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+
+    // Compare canonical paths so symlinks can't lead outside the root
+    let root = root.canonicalize().ok()?;
+    let path = root.join(relative).canonicalize().ok()?;
+    path.starts_with(&root).then_some(path)
+}
+
+// Reads the requested file, or the requested range of it
+fn respond(request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, std::io::Error> {
+    if request.method() != tauri::http::Method::GET {
+        return Ok(error_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let Some(path) = configured_root().and_then(|root| requested_file(request, &root)) else {
+        return Ok(error_response(StatusCode::NOT_FOUND));
+    };
+
+    let mut file = std::fs::File::open(&path)?;
+    let len = file.metadata()?.len();
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range(value, len));
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type(&path))
+        .header(header::ACCEPT_RANGES, "bytes");
+    let response = match range {
+        None => {
+            let mut data = Vec::with_capacity(len as usize);
+            file.read_to_end(&mut data)?;
+            response.body(data)
+        },
+        Some(None) => response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Vec::new()),
+        Some(Some((start, end))) => {
+            let mut data = vec![0; (end - start + 1) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut data)?;
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .body(data)
+        },
+    };
+    Ok(response.unwrap_or_default())
+}
+
+/// Handles attachment://localhost/<path below the root> requests, reading the
+/// file on the blocking thread pool.
+pub fn serve(request: Request<Vec<u8>>, responder: UriSchemeResponder) {
+    tauri::async_runtime::spawn_blocking(move || {
+        let response = respond(&request).unwrap_or_else(|e| {
+            log::warn!("Could not read attachment {}: {}", request.uri().path(), e);
+            error_response(StatusCode::NOT_FOUND)
+        });
+        responder.respond(response);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn request(path: &str) -> Request<Vec<u8>> {
+        Request::builder().uri(format!("attachment://localhost{}", path)).body(Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn relative_path_is_below_attachments() {
+        assert_eq!(
+            relative_path("~/Library/Messages/Attachments/ab/12/GUID/IMG_0001.HEIC"),
+            Some(PathBuf::from("ab/12/GUID/IMG_0001.HEIC"))
+        );
+        assert_eq!(relative_path("~/Library/Messages/StickerCache/sticker.png"), None);
+        assert_eq!(relative_path("~/Library/Messages/Attachments/ab/../../../chat.db"), None);
+        assert_eq!(relative_path("~/Library/Messages/Attachments//etc/passwd"), None);
+    }
+
+    #[test]
+    fn urls_escape_each_segment() {
+        let relative = PathBuf::from("ab/12/My Photo #1?.jpg");
+        assert_eq!(url_for(&relative), "attachment://localhost/ab/12/My%20Photo%20%231%3F.jpg");
+    }
+
+    #[test]
+    fn requested_file_stays_inside_the_root() {
+        let dir = std::env::temp_dir().join(format!("attachments-test-{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("ab")).unwrap();
+        std::fs::write(root.join("ab/My Photo.jpg"), b"jpeg").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("ab/link.txt")).unwrap();
+
+        let found = requested_file(&request("/ab/My%20Photo.jpg"), &root).unwrap();
+        assert!(found.ends_with("ab/My Photo.jpg"));
+        for path in ["/../secret.txt", "/ab/%2E%2E/%2E%2E/secret.txt", "/ab/..%2F..%2Fsecret.txt", "/", "/ab/missing.jpg"] {
+            assert_eq!(requested_file(&request(path), &root), None, "{}", path);
+        }
+        #[cfg(unix)]
+        assert_eq!(requested_file(&request("/ab/link.txt"), &root), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Some((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Some((0, 999))));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some(Some((990, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(None));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(None));
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn ranges_are_cut_to_the_limit() {
+        assert_eq!(parse_range("bytes=0-", 100 * MB), Some(Some((0, MAX_RANGE_BYTES - 1))));
+        assert_eq!(
+            parse_range(&format!("bytes={}-{}", MB, 100 * MB - 1), 100 * MB),
+            Some(Some((MB, MB + MAX_RANGE_BYTES - 1)))
+        );
+    }
+}
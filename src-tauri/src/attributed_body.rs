@@ -0,0 +1,152 @@
+// Reading attributes out of message.attributedBody.
+//
+// attributedBody is an NSAttributedString serialized with NSArchiver's
+// typedstream format. Rather than decode the whole object graph, we look for
+// the attribute names we care about and read the string object that follows
+// each one. Strings in a typedstream are a '+' type byte, a length and UTF-8.
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+
+// Attribute set on a confirmed @-mention; its value is the mentioned handle
+const MENTION_ATTRIBUTE: &[u8] = b"__kIMMentionConfirmedMention";
+
+// How far past an attribute name its value may start
+const VALUE_SEARCH_WINDOW: usize = 128;
+
+const STRING_TYPE: u8 = b'+';
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+// Reads a typedstream string whose type byte is at `start`
+fn read_string(data: &[u8], start: usize) -> Option<&str> {
+    if *data.get(start)? != STRING_TYPE {
+        return None;
+    }
+    let (len, offset) = match *data.get(start + 1)? {
+        0x81 => (u16::from_le_bytes([*data.get(start + 2)?, *data.get(start + 3)?]) as usize, start + 4),
+        0x82 => (
+            u32::from_le_bytes(data.get(start + 2..start + 6)?.try_into().ok()?) as usize,
+            start + 6,
+        ),
+        len => (len as usize, start + 2),
+    };
+    std::str::from_utf8(data.get(offset..offset + len)?).ok()
+}
+
+fn looks_like_handle(value: &str) -> bool {
+    !value.is_empty()
+        && (value.contains('@')
+            || value.chars().all(|c| c.is_ascii_digit() || "+-() ".contains(c)))
+}
+
+/// Handles @-mentioned in a message, in the order they appear.
+pub fn mentioned_handles(attributed_body: &[u8]) -> Vec<String> {
+    let mut handles: Vec<String> = Vec::new();
+    let mut from = 0;
+
+    while let Some(position) = find(attributed_body, MENTION_ATTRIBUTE, from) {
+        from = position + MENTION_ATTRIBUTE.len();
+        let window_end = (from + VALUE_SEARCH_WINDOW).min(attributed_body.len());
+
+        let value = (from..window_end)
+            .filter(|&i| attributed_body[i] == STRING_TYPE)
+            .find_map(|i| read_string(attributed_body, i).filter(|s| looks_like_handle(s)));
+        if let Some(handle) = value {
+            if !handles.iter().any(|h| h == handle) {
+                handles.push(handle.to_string());
+            }
+        }
+    }
+    handles
+}
+
+/// Registers `mentions_match(attributedBody, handles)`, which is true when the
+/// message mentions any of the newline-separated handles.
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "mentions_match",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let wanted = ctx.get_or_create_aux(1, |vr| -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+                Ok(vr.as_str()?.lines().map(crate::handle_key).filter(|k| !k.is_empty()).collect())
+            })?;
+            let body: Option<Vec<u8>> = ctx.get(0)?;
+            Ok(body.is_some_and(|body| {
+                mentioned_handles(&body)
+                    .iter()
+                    .any(|handle| wanted.contains(&crate::handle_key(handle)))
+            }))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = vec![STRING_TYPE];
+        if value.len() < 0x80 {
+            bytes.push(value.len() as u8);
+        } else {
+            bytes.push(0x81);
+            bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        }
+        bytes.extend_from_slice(value.as_bytes());
+        bytes
+    }
+
+    fn mention(handle: &str) -> Vec<u8> {
+        let mut bytes = MENTION_ATTRIBUTE.to_vec();
+        bytes.extend_from_slice(&[0x86, 0x84, 0x01, 0x40]);
+        bytes.extend(string(handle));
+        bytes
+    }
+
+    #[test]
+    fn reads_mentions_in_order_without_duplicates() {
+        let mut body = b"streamtyped\x81\xe8\x03".to_vec();
+        body.extend(string("hey @Ann and @Bob"));
+        body.extend(mention("+15551234567"));
+        body.extend(mention("bob@example.com"));
+        body.extend(mention("+15551234567"));
+        assert_eq!(mentioned_handles(&body), vec!["+15551234567", "bob@example.com"]);
+    }
+
+    #[test]
+    fn skips_values_that_are_not_handles() {
+        let mut body = MENTION_ATTRIBUTE.to_vec();
+        body.extend(string("NSColor"));
+        body.extend(string("+1 (555) 123-4567"));
+        assert_eq!(mentioned_handles(&body), vec!["+1 (555) 123-4567"]);
+    }
+
+    #[test]
+    fn reads_long_strings() {
+        let handle = format!("{}@example.com", "a".repeat(200));
+        assert_eq!(mentioned_handles(&mention(&handle)), vec![handle]);
+    }
+
+    #[test]
+    fn ignores_truncated_and_plain_bodies() {
+        assert!(mentioned_handles(b"just some text").is_empty());
+        let mut body = MENTION_ATTRIBUTE.to_vec();
+        body.extend_from_slice(&[STRING_TYPE, 40, b'+', b'1']);
+        assert!(mentioned_handles(&body).is_empty());
+    }
+
+    #[test]
+    fn value_must_follow_the_attribute_closely() {
+        let mut body = MENTION_ATTRIBUTE.to_vec();
+        body.extend(vec![0; VALUE_SEARCH_WINDOW]);
+        body.extend(string("+15551234567"));
+        assert!(mentioned_handles(&body).is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+// Stars, notes and tags on messages.
+//
+// Bookmarks live in the app database keyed by message guid, because ROWIDs in
+// chat.db change when Messages rebuilds it. A bookmark row exists while a
+// message is starred or has a note or tags, and is removed once all are cleared.
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{aliases, app_db, export, AppError, Message};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Bookmark {
+    message_guid: String,
+    starred: bool,
+    note: Option<String>,
+    tags: Vec<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BookmarkedMessage {
+    bookmark: Bookmark,
+    // None if the message is no longer in chat.db
+    message: Option<Message>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TagCount {
+    tag: String,
+    count: i64,
+}
+
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_string();
+        if !tag.is_empty() && !normalized.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+// Bookmarks for the given guids, or all bookmarks when `guids` is None
+fn load_bookmarks(conn: &Connection, guids: Option<&[String]>) -> Result<Vec<Bookmark>, AppError> {
+    let mut sql = "SELECT message_guid, starred, note, created_at, updated_at FROM bookmark".to_string();
+    if let Some(guids) = guids {
+        if guids.is_empty() {
+            return Ok(Vec::new());
+        }
+        sql.push_str(&format!(" WHERE message_guid IN ({})", vec!["?"; guids.len()].join(", ")));
+    }
+    sql.push_str(" ORDER BY created_at DESC");
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut bookmarks = stmt
+        .query_map(rusqlite::params_from_iter(guids.unwrap_or(&[])), |row| {
+            Ok(Bookmark {
+                message_guid: row.get(0)?,
+                starred: row.get(1)?,
+                note: row.get(2)?,
+                created_at: row.get(3)?,
+                updated_at: row.get(4)?,
+                tags: Vec::new(),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut tag_stmt = conn.prepare("SELECT tag FROM bookmark_tag WHERE message_guid = ? ORDER BY tag")?;
+    for bookmark in &mut bookmarks {
+        bookmark.tags = tag_stmt
+            .query_map([&bookmark.message_guid], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+    }
+    Ok(bookmarks)
+}
+
+/// Sets `bookmark` on each message that has one. Failures are logged, not returned,
+/// since bookmarks are an extra on top of the messages themselves.
+pub fn annotate(messages: &mut [Message]) {
+    if messages.is_empty() {
+        return;
+    }
+    let guids: Vec<String> = messages.iter().map(|m| m.guid.clone()).filter(|g| !g.is_empty()).collect();
+    let bookmarks = match app_db::open_app_db().and_then(|conn| load_bookmarks(&conn, Some(&guids))) {
+        Ok(bookmarks) => bookmarks,
+        Err(e) => {
+            log::warn!("Failed to load bookmarks: {}", e);
+            return;
+        }
+    };
+    for message in messages {
+        message.bookmark = bookmarks.iter().find(|b| b.message_guid == message.guid).cloned();
+    }
+}
+
+/// Stars, notes or tags a message. Clearing all three removes the bookmark.
+#[tauri::command]
+pub async fn set_bookmark(
+    message_guid: String,
+    starred: bool,
+    note: Option<String>,
+    tags: Vec<String>,
+) -> Result<Option<Bookmark>, AppError> {
+    let note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    let tags = normalize_tags(tags);
+    let mut conn = app_db::open_app_db()?;
+
+    if !starred && note.is_none() && tags.is_empty() {
+        conn.execute("DELETE FROM bookmark WHERE message_guid = ?", [&message_guid])?;
+        return Ok(None);
+    }
+
+    let chat_conn = crate::open_imessage_db()?;
+    if crate::message_by_guid(&chat_conn, &message_guid)?.is_none() {
+        return Err(AppError::OtherError(format!("Message {} not found", message_guid)));
+    }
+
+    let now = app_db::now();
+    let tx = conn.transaction()?;
+    tx.execute(
+        r#"
+        INSERT INTO bookmark (message_guid, starred, note, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?4)
+        ON CONFLICT(message_guid) DO UPDATE SET
+            starred = excluded.starred,
+            note = excluded.note,
+            updated_at = excluded.updated_at
+        "#,
+        rusqlite::params![message_guid, starred, note, now],
+    )?;
+    tx.execute("DELETE FROM bookmark_tag WHERE message_guid = ?", [&message_guid])?;
+    for tag in &tags {
+        tx.execute(
+            "INSERT INTO bookmark_tag (message_guid, tag) VALUES (?, ?)",
+            [&message_guid, tag],
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(load_bookmarks(&conn, Some(&[message_guid]))?.pop())
+}
+
+#[tauri::command]
+pub async fn delete_bookmark(message_guid: String) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    conn.execute("DELETE FROM bookmark WHERE message_guid = ?", [message_guid])?;
+    Ok(())
+}
+
+// Bookmarks with their messages, newest bookmark first, optionally limited to a tag
+fn bookmarked_messages(tag: Option<&str>) -> Result<Vec<BookmarkedMessage>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut bookmarks = load_bookmarks(&conn, None)?;
+    if let Some(tag) = tag {
+        bookmarks.retain(|b| b.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+    }
+
+    let chat_conn = crate::open_imessage_db()?;
+    let aliases = aliases::Aliases::load();
+    let mut items = Vec::with_capacity(bookmarks.len());
+    for bookmark in bookmarks {
+        let mut message = crate::message_by_guid(&chat_conn, &bookmark.message_guid)?;
+        if let Some(message) = &mut message {
+            message.bookmark = Some(bookmark.clone());
+            aliases.apply(message);
+        }
+        items.push(BookmarkedMessage { bookmark, message });
+    }
+    Ok(items)
+}
+
+#[tauri::command]
+pub async fn list_bookmarks(tag: Option<String>) -> Result<Vec<BookmarkedMessage>, AppError> {
+    bookmarked_messages(tag.as_deref())
+}
+
+#[tauri::command]
+pub async fn list_bookmark_tags() -> Result<Vec<TagCount>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut stmt = conn.prepare(
+        "SELECT tag, COUNT(*) FROM bookmark_tag GROUP BY tag ORDER BY COUNT(*) DESC, tag",
+    )?;
+    let tags = stmt
+        .query_map([], |row| Ok(TagCount { tag: row.get(0)?, count: row.get(1)? }))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(tags)
+}
+
+/// Writes bookmarks as "json" or "csv" to `path` (default: Downloads) and returns the path.
+#[tauri::command]
+pub async fn export_bookmarks(format: String, path: Option<String>, tag: Option<String>) -> Result<String, AppError> {
+    let items = bookmarked_messages(tag.as_deref())?;
+
+    let contents = match format.as_str() {
+        "json" => serde_json::to_string_pretty(&items)?,
+        "csv" => {
+            let mut csv = export::csv_row(&["date", "conversation", "sender", "text", "starred", "note", "tags", "message_guid"]);
+            for item in &items {
+                let message = item.message.as_ref();
+                let date = message
+                    .and_then(|m| chrono::DateTime::from_timestamp(m.date, 0))
+                    .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+                let sender = match message {
+                    Some(m) if m.is_from_me => "me".to_string(),
+                    Some(m) => m.sender_name.clone().unwrap_or_default(),
+                    None => String::new(),
+                };
+                csv.push_str(&export::csv_row(&[
+                    &date,
+                    message.and_then(|m| m.conversation_name.as_deref()).unwrap_or(""),
+                    &sender,
+                    message.map(|m| m.text.as_str()).unwrap_or(""),
+                    if item.bookmark.starred { "yes" } else { "no" },
+                    item.bookmark.note.as_deref().unwrap_or(""),
+                    &item.bookmark.tags.join("; "),
+                    &item.bookmark.message_guid,
+                ]));
+            }
+            csv
+        },
+        other => return Err(AppError::OtherError(format!("Unsupported export format: {}", other))),
+    };
+
+    export::write_export(export::export_path(path, "imessage-bookmarks", &format)?, &contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_trimmed_and_deduplicated() {
+        let tags = vec![" Work ".to_string(), "work".to_string(), "".to_string(), "Trip".to_string()];
+        assert_eq!(normalize_tags(tags), vec!["Work", "Trip"]);
+    }
+
+    #[test]
+    fn loads_bookmarks_with_their_tags() {
+        let conn = app_db::open_test_db();
+        conn.execute_batch(
+            r#"
+            INSERT INTO bookmark VALUES ('a', 1, NULL, 1, 1), ('b', 0, 'call back', 2, 2);
+            INSERT INTO bookmark_tag VALUES ('b', 'work'), ('b', 'Trip');
+            "#,
+        )
+        .unwrap();
+
+        let bookmarks = load_bookmarks(&conn, Some(&["b".to_string(), "missing".to_string()])).unwrap();
+        assert_eq!(bookmarks.len(), 1);
+        assert_eq!(bookmarks[0].note.as_deref(), Some("call back"));
+        assert_eq!(bookmarks[0].tags, vec!["Trip", "work"]);
+
+        let all: Vec<String> = load_bookmarks(&conn, None).unwrap().into_iter().map(|b| b.message_guid).collect();
+        assert_eq!(all, vec!["b", "a"]);
+        assert!(load_bookmarks(&conn, Some(&[])).unwrap().is_empty());
+    }
+}
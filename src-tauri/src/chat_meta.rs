@@ -0,0 +1,100 @@
+// Per-conversation settings kept by the app: pinned, hidden, color and labels.
+//
+// Stored in the app database keyed by chat guid. get_conversations joins
+// chat_meta through the attached app database to order and hide chats, and
+// search can be limited to chats carrying a label.
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{app_db, AppError};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChatMeta {
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub color: Option<String>,   // "#rrggbb"
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LabelCount {
+    label: String,
+    count: i64,
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Labels for every labeled chat, keyed by chat guid.
+pub fn labels_by_chat(conn: &Connection) -> Result<HashMap<String, Vec<String>>, AppError> {
+    let mut stmt = conn.prepare("SELECT chat_guid, label FROM chat_label ORDER BY label")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+    let mut labels: HashMap<String, Vec<String>> = HashMap::new();
+    for row in rows {
+        let (guid, label) = row?;
+        labels.entry(guid).or_default().push(label);
+    }
+    Ok(labels)
+}
+
+#[tauri::command]
+pub async fn set_chat_meta(chat_guid: String, meta: ChatMeta) -> Result<ChatMeta, AppError> {
+    let color = meta.color.map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty());
+    if let Some(color) = &color {
+        if !is_hex_color(color) {
+            return Err(AppError::OtherError(format!("Invalid color: {}", color)));
+        }
+    }
+
+    let mut labels: Vec<String> = Vec::new();
+    for label in meta.labels {
+        let label = label.trim().to_string();
+        if !label.is_empty() && !labels.iter().any(|l| l.eq_ignore_ascii_case(&label)) {
+            labels.push(label);
+        }
+    }
+
+    let mut conn = app_db::open_app_db()?;
+    let tx = conn.transaction()?;
+    if !meta.pinned && !meta.hidden && color.is_none() && labels.is_empty() {
+        tx.execute("DELETE FROM chat_meta WHERE chat_guid = ?", [&chat_guid])?;
+    } else {
+        tx.execute(
+            r#"
+            INSERT INTO chat_meta (chat_guid, pinned, hidden, color, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(chat_guid) DO UPDATE SET
+                pinned = excluded.pinned,
+                hidden = excluded.hidden,
+                color = excluded.color,
+                updated_at = excluded.updated_at
+            "#,
+            rusqlite::params![chat_guid, meta.pinned, meta.hidden, color, app_db::now()],
+        )?;
+    }
+    tx.execute("DELETE FROM chat_label WHERE chat_guid = ?", [&chat_guid])?;
+    for label in &labels {
+        tx.execute("INSERT INTO chat_label (chat_guid, label) VALUES (?, ?)", [&chat_guid, label])?;
+    }
+    tx.commit()?;
+
+    Ok(ChatMeta { pinned: meta.pinned, hidden: meta.hidden, color, labels })
+}
+
+#[tauri::command]
+pub async fn list_chat_labels() -> Result<Vec<LabelCount>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut stmt = conn.prepare(
+        "SELECT label, COUNT(*) FROM chat_label GROUP BY label ORDER BY label",
+    )?;
+    let labels = stmt
+        .query_map([], |row| Ok(LabelCount { label: row.get(0)?, count: row.get(1)? }))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(labels)
+}
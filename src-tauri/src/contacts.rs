@@ -0,0 +1,108 @@
+// Contact cards from the macOS AddressBook, looked up by handle.
+//
+// This reads the same AddressBook records as read_contacts, reduced to a name
+// per phone number and email address. Opening the AddressBook copies its
+// database, so the directory is loaded once and reused for a few minutes.
+// Handles are matched by handle_key, so any spelling of a number finds its
+// card.
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{handle_key, text, AppError};
+
+// How long a loaded directory is used before the AddressBook is read again
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct ContactCard {
+    // Z_PK of the AddressBook record, shared by all of a contact's handles
+    pub id: i64,
+    pub name: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ContactDirectory {
+    // Card by handle key
+    cards: HashMap<String, ContactCard>,
+}
+
+impl ContactDirectory {
+    fn load() -> Result<Self, AppError> {
+        let path = crate::get_addressbook_db_path()?;
+        let conn = Connection::open(path).map_err(AppError::DatabaseConnectionError)?;
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT
+                COALESCE(
+                    NULLIF(TRIM(COALESCE(r.ZFIRSTNAME, '') || ' ' || COALESCE(r.ZLASTNAME, '')), ''),
+                    r.ZNICKNAME,
+                    r.ZORGANIZATION
+                ) as name,
+                h.handle,
+                r.Z_PK
+            FROM
+                ZABCDRECORD r
+            JOIN (
+                SELECT ZOWNER, ZADDRESS as handle FROM ZABCDEMAILADDRESS WHERE ZADDRESS IS NOT NULL
+                UNION ALL
+                SELECT ZOWNER, ZFULLNUMBER as handle FROM ZABCDPHONENUMBER WHERE ZFULLNUMBER IS NOT NULL
+            ) h ON h.ZOWNER = r.Z_PK
+            "#,
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?;
+
+        let mut directory = ContactDirectory::default();
+        for row in rows {
+            let (name, handle, id) = row?;
+            let key = handle_key(&handle);
+            if !key.is_empty() {
+                directory.cards.insert(key, ContactCard { id, name });
+            }
+        }
+        Ok(directory)
+    }
+
+    pub fn card(&self, handle: &str) -> Option<&ContactCard> {
+        self.cards.get(&handle_key(handle))
+    }
+
+    pub fn name(&self, handle: &str) -> Option<&str> {
+        self.card(handle).and_then(|card| card.name.as_deref())
+    }
+
+    /// Handle keys of every contact whose name contains `folded_name`, which
+    /// must already be folded.
+    pub fn handle_keys_named(&self, folded_name: &str) -> Vec<String> {
+        if folded_name.is_empty() {
+            return Vec::new();
+        }
+        self.cards
+            .iter()
+            .filter(|(_, card)| card.name.as_deref().is_some_and(|name| text::fold_text(name).contains(folded_name)))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+/// The contact directory, read again once it is older than CACHE_TTL. An
+/// unreadable AddressBook is logged and gives an empty directory.
+pub fn directory() -> Arc<ContactDirectory> {
+    static CACHE: Mutex<Option<(Instant, Arc<ContactDirectory>)>> = Mutex::new(None);
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((loaded_at, directory)) = cache.as_ref() {
+        if loaded_at.elapsed() < CACHE_TTL {
+            return directory.clone();
+        }
+    }
+    let directory = Arc::new(ContactDirectory::load().unwrap_or_else(|e| {
+        log::warn!("Contacts unavailable: {}", e);
+        ContactDirectory::default()
+    }));
+    *cache = Some((Instant::now(), directory.clone()));
+    directory
+}
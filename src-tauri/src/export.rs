@@ -0,0 +1,68 @@
+// Shared helpers for commands that export data to a file.
+use std::path::PathBuf;
+
+use crate::AppError;
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+/// Text a spreadsheet would run as a formula is prefixed with `'` and quoted,
+/// since notes, captions and names can come from other people.
+pub fn csv_field(value: &str) -> String {
+    let is_formula = value.starts_with(['=', '+', '-', '@', '\t', '\r']) && value.parse::<f64>().is_err();
+    if is_formula {
+        format!("\"'{}\"", value.replace('"', "\"\""))
+    } else if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn csv_row(fields: &[&str]) -> String {
+    let mut row = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(",");
+    row.push_str("\r\n");
+    row
+}
+
+/// Where to write an export: `path` when given, otherwise a dated file in Downloads.
+pub fn export_path(path: Option<String>, stem: &str, extension: &str) -> Result<PathBuf, AppError> {
+    if let Some(path) = path {
+        return Ok(PathBuf::from(path));
+    }
+    let dir = dirs::download_dir()
+        .or_else(dirs::home_dir)
+        .ok_or(AppError::OtherError("Home directory not found".to_string()))?;
+    let date = chrono::Local::now().format("%Y-%m-%d");
+    Ok(dir.join(format!("{}-{}.{}", stem, date, extension)))
+}
+
+/// Writes `contents` to `path`, creating parent directories, and returns the path.
+pub fn write_export(path: PathBuf, contents: &str) -> Result<String, AppError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, contents)?;
+    log::info!("Exported to {:?}", path);
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_separators() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+        assert_eq!(csv_row(&["1", "two\nlines"]), "1,\"two\nlines\"\r\n");
+    }
+
+    #[test]
+    fn formulas_are_not_run() {
+        assert_eq!(csv_field("=HYPERLINK(\"http://x\")"), "\"'=HYPERLINK(\"\"http://x\"\")\"");
+        assert_eq!(csv_field("+1 555"), "\"'+1 555\"");
+        assert_eq!(csv_field("@SUM(A1)"), "\"'@SUM(A1)\"");
+        assert_eq!(csv_field("-2+3"), "\"'-2+3\"");
+        // Numbers stay numbers
+        assert_eq!(csv_field("-15.05"), "-15.05");
+    }
+}
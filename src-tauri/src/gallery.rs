@@ -0,0 +1,359 @@
+// Browsing attachments across all conversations or within one.
+//
+// Attachments are listed newest or largest first and paged with an opaque
+// cursor holding the last row's sort key and ROWID, so pages stay stable while
+// new messages arrive. Categories are the ones the attachment_type search
+// filter uses. A contact filter keeps the chats the contact takes part in, so
+// attachments the user sent them are listed too.
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{aliases::Aliases, attachments, thumbnails, AppError, ContactIdentifier};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum GallerySort {
+    #[default]
+    Date,
+    Size,
+}
+
+impl GallerySort {
+    fn key_sql(self) -> &'static str {
+        match self {
+            GallerySort::Date => "m.date",
+            GallerySort::Size => "COALESCE(a.total_bytes, 0)",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct AttachmentFilter {
+    #[serde(default)]
+    conversation_id: Option<String>,     // may list merged chat ids
+    #[serde(default)]
+    contact_identifiers: Vec<ContactIdentifier>,
+    #[serde(default)]
+    start_date: Option<String>,          // yyyy-MM-dd format
+    #[serde(default)]
+    end_date: Option<String>,            // yyyy-MM-dd format
+    #[serde(default)]
+    category: Option<String>,            // "image", "video", "pdf", "audio" or "other"
+    #[serde(default)]
+    sort: GallerySort,
+    #[serde(default)]
+    ascending: bool,
+    #[serde(default)]
+    include_stickers: bool,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GalleryAttachment {
+    id: i64,
+    guid: String,
+    kind: String,                        // category, as in the attachment_type filter
+    mime_type: Option<String>,
+    transfer_name: Option<String>,
+    total_bytes: i64,
+    date: i64,
+    message_id: i64,
+    message_guid: String,
+    chat_id: String,
+    conversation_name: Option<String>,
+    is_from_me: bool,
+    sender_name: Option<String>,
+    sender_handle: Option<String>,
+    path: Option<String>,
+    url: Option<String>,
+    thumbnail_url: Option<String>,
+    state: attachments::AttachmentState,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AttachmentPage {
+    attachments: Vec<GalleryAttachment>,
+    // Pass to get_attachments for the next page; None on the last page
+    next_cursor: Option<String>,
+}
+
+// "<sort key>:<attachment ROWID>" of the last row of a page
+fn parse_cursor(cursor: &str) -> Result<(i64, i64), AppError> {
+    cursor
+        .split_once(':')
+        .and_then(|(key, id)| Some((key.parse().ok()?, id.parse().ok()?)))
+        .ok_or_else(|| AppError::OtherError(format!("Invalid cursor: {}", cursor)))
+}
+
+// Chats with a participant matching a condition on handle h
+fn participant_chats_sql(condition: &str) -> String {
+    format!(
+        "cmj.chat_id IN (SELECT chj.chat_id FROM chat_handle_join chj JOIN handle h ON h.ROWID = chj.handle_id WHERE {})",
+        condition
+    )
+}
+
+/// One page of attachments matching `filter`, continuing after `cursor`.
+#[tauri::command]
+pub async fn get_attachments(filter: AttachmentFilter, cursor: Option<String>) -> Result<AttachmentPage, AppError> {
+    log::info!("Listing attachments: {:?}", filter);
+    let conn = crate::open_imessage_db()?;
+    let page = list_attachments(&conn, &filter, cursor.as_deref(), &attachments::Resolver::load(), &Aliases::load())?;
+    log::info!("Found {} attachments", page.attachments.len());
+    Ok(page)
+}
+
+fn list_attachments(
+    conn: &Connection,
+    filter: &AttachmentFilter,
+    cursor: Option<&str>,
+    resolver: &attachments::Resolver,
+    aliases: &Aliases,
+) -> Result<AttachmentPage, AppError> {
+    let key = filter.sort.key_sql();
+    let limit = filter.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let mut sql = format!(
+        r#"
+        SELECT
+            a.ROWID,
+            a.guid,
+            a.filename,
+            a.transfer_name,
+            a.mime_type,
+            COALESCE(a.total_bytes, 0),
+            {} as category,
+            m.ROWID,
+            m.guid,
+            m.date,
+            m.is_from_me,
+            cmj.chat_id,
+            c.display_name,
+            (SELECT ph.id FROM chat_handle_join pj JOIN handle ph ON ph.ROWID = pj.handle_id
+             WHERE pj.chat_id = c.ROWID LIMIT 1),
+            COALESCE(h.uncanonicalized_id, h.id),
+            COALESCE(a.ck_record_id, '') != '',
+            {} as sort_key
+        FROM
+            attachment a
+        JOIN
+            message_attachment_join maj ON maj.attachment_id = a.ROWID
+        JOIN
+            message m ON m.ROWID = maj.message_id
+        JOIN
+            chat_message_join cmj ON cmj.message_id = m.ROWID
+        JOIN
+            chat c ON c.ROWID = cmj.chat_id
+        LEFT JOIN
+            handle h ON m.handle_id = h.ROWID
+        WHERE 1=1
+        "#,
+        crate::ATTACHMENT_CATEGORY_SQL,
+        key
+    );
+    let mut query_params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+    // Add conversation filter
+    if let Some(conversation_id) = &filter.conversation_id {
+        let chat_ids: Vec<String> = crate::merge::parse_chat_ids(conversation_id)?.iter().map(|id| id.to_string()).collect();
+        sql.push_str(&format!(" AND cmj.chat_id IN ({})", chat_ids.join(", ")));
+    }
+
+    // Add contact filter
+    if let Some(condition) = crate::contact_identifiers_sql(&filter.contact_identifiers, aliases) {
+        sql.push_str(&format!(" AND {}", participant_chats_sql(&condition)));
+    }
+
+    // Add date filters
+    for (date, op) in [(&filter.start_date, ">"), (&filter.end_date, "<")] {
+        if let Some(date) = date {
+            let timestamp = crate::date_to_apple_timestamp(date)
+                .ok_or_else(|| AppError::OtherError(format!("Invalid date: {}", date)))?;
+            sql.push_str(&format!(" AND m.date {} {}", op, timestamp));
+        }
+    }
+
+    // Add category filter
+    if let Some(category) = filter.category.as_deref().filter(|c| *c != "all") {
+        sql.push_str(&format!(" AND {} = ?", crate::ATTACHMENT_CATEGORY_SQL));
+        query_params.push(Box::new(category.to_string()));
+    }
+
+    if !filter.include_stickers {
+        sql.push_str(" AND COALESCE(a.is_sticker, 0) = 0");
+    }
+
+    // Continue after the cursor
+    let (order, compare) = if filter.ascending { ("ASC", ">") } else { ("DESC", "<") };
+    if let Some(cursor) = cursor {
+        let (last_key, last_id) = parse_cursor(cursor)?;
+        sql.push_str(&format!(
+            " AND ({key} {compare} ? OR ({key} = ? AND a.ROWID {compare} ?))",
+            key = key,
+            compare = compare
+        ));
+        query_params.push(Box::new(last_key));
+        query_params.push(Box::new(last_key));
+        query_params.push(Box::new(last_id));
+    }
+
+    // One extra row tells whether there is another page
+    sql.push_str(&format!(" ORDER BY sort_key {order}, a.ROWID {order} LIMIT {}", limit + 1, order = order));
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(query_params.iter().map(|p| p.as_ref())))?;
+
+    let mut attachments = Vec::new();
+    let mut last_key: Option<(i64, i64)> = None;
+    let mut has_more = false;
+    while let Some(row) = rows.next()? {
+        // A row beyond the limit means another page exists
+        if attachments.len() == limit {
+            has_more = true;
+            break;
+        }
+        let id: i64 = row.get(0)?;
+        let guid: String = row.get(1)?;
+        let filename: Option<String> = row.get(2)?;
+        let kind: String = row.get(6)?;
+        let date: i64 = row.get(9)?;
+        let is_from_me = row.get::<_, i64>(10)? == 1;
+        let in_icloud: bool = row.get(15)?;
+        last_key = Some((row.get(16)?, id));
+
+        let resolved = filename.as_deref().map(|f| resolver.resolve(f));
+        let exists = resolved.as_ref().is_some_and(|r| r.exists);
+        let state = match (exists, in_icloud) {
+            (true, _) => attachments::AttachmentState::Present,
+            (false, true) => attachments::AttachmentState::Offloaded,
+            (false, false) => attachments::AttachmentState::Missing,
+        };
+        let (path, url) = resolved.map_or((None, None), |r| (r.path, r.url));
+
+        let sender_handle: Option<String> = if is_from_me { None } else { row.get(14)? };
+        let sender_name = sender_handle
+            .as_deref()
+            .map(|h| aliases.display_name(h).unwrap_or(h).to_string());
+
+        attachments.push(GalleryAttachment {
+            id,
+            thumbnail_url: (kind == "image" && exists).then(|| thumbnails::thumbnail_url(&guid, thumbnails::DEFAULT_SIZE)),
+            guid,
+            kind,
+            mime_type: row.get(4)?,
+            transfer_name: row.get(3)?,
+            total_bytes: row.get(5)?,
+            date: crate::apple_time_to_unix(date / 1_000_000_000),
+            message_id: row.get(7)?,
+            message_guid: row.get(8)?,
+            chat_id: row.get::<_, i64>(11)?.to_string(),
+            conversation_name: crate::conversation_name(row.get(12)?, row.get(13)?, aliases),
+            is_from_me,
+            sender_name,
+            sender_handle,
+            path,
+            url,
+            state,
+        });
+    }
+
+    let next_cursor = if has_more { last_key.map(|(key, id)| format!("{}:{}", key, id)) } else { None };
+
+    Ok(AttachmentPage { attachments, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_db;
+
+    // Chat 1 is with Ann, chat 2 is a group with Ann and Bob, chat 3 is with
+    // Bob. Each message has one attachment with the same ROWID.
+    fn test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE attachment (ROWID INTEGER PRIMARY KEY, guid TEXT, filename TEXT, transfer_name TEXT,
+                mime_type TEXT, total_bytes INTEGER, ck_record_id TEXT, is_sticker INTEGER);
+            CREATE TABLE message_attachment_join (message_id INTEGER, attachment_id INTEGER);
+            CREATE TABLE message (ROWID INTEGER PRIMARY KEY, guid TEXT, date INTEGER, is_from_me INTEGER, handle_id INTEGER);
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+            CREATE TABLE chat (ROWID INTEGER PRIMARY KEY, display_name TEXT);
+            CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT, uncanonicalized_id TEXT);
+            CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER);
+            INSERT INTO handle VALUES (1, '+15551234567', NULL), (2, 'bob@example.com', NULL);
+            INSERT INTO chat VALUES (1, ''), (2, 'Trip'), (3, NULL);
+            INSERT INTO chat_handle_join VALUES (1, 1), (2, 1), (2, 2), (3, 2);
+            INSERT INTO message VALUES
+                (1, 'm1', 1000000000, 0, 1),
+                (2, 'm2', 2000000000, 1, 0),
+                (3, 'm3', 3000000000, 0, 2),
+                (4, 'm4', 4000000000, 1, 0);
+            INSERT INTO chat_message_join VALUES (1, 1), (1, 2), (2, 3), (3, 4);
+            INSERT INTO attachment SELECT ROWID, 'a' || ROWID, NULL, 'photo.jpg', 'image/jpeg', ROWID * 10, NULL, 0 FROM message;
+            INSERT INTO message_attachment_join SELECT ROWID, ROWID FROM message;
+            "#,
+        )
+        .unwrap();
+        conn
+    }
+
+    // Ann's number has a nickname and Bob is a local person
+    fn test_aliases() -> Aliases {
+        let conn = app_db::open_test_db();
+        conn.execute_batch(
+            r#"
+            INSERT INTO person (id, name, created_at, updated_at) VALUES (1, 'Bob', 0, 0);
+            INSERT INTO handle_alias (handle_key, handle, display_name) VALUES ('5551234567', '+15551234567', 'Ann');
+            INSERT INTO handle_alias (handle_key, handle, person_id) VALUES ('bob@example.com', 'bob@example.com', 1);
+            "#,
+        )
+        .unwrap();
+        Aliases::load_from(&conn).unwrap()
+    }
+
+    fn list(filter: &AttachmentFilter, cursor: Option<&str>) -> AttachmentPage {
+        list_attachments(&test_db(), filter, cursor, &attachments::Resolver::with_root(None), &test_aliases()).unwrap()
+    }
+
+    fn ids(page: &AttachmentPage) -> Vec<i64> {
+        page.attachments.iter().map(|a| a.id).collect()
+    }
+
+    #[test]
+    fn contact_filter_keeps_chats_the_contact_is_in() {
+        let ann = ContactIdentifier { contact_id: None, phones: vec!["(555) 123-4567".to_string()], emails: vec![], person_id: None };
+        let filter = AttachmentFilter { contact_identifiers: vec![ann], ..Default::default() };
+        assert_eq!(ids(&list(&filter, None)), vec![3, 2, 1]);
+
+        let bob = ContactIdentifier { contact_id: None, phones: vec![], emails: vec![], person_id: Some(1) };
+        let filter = AttachmentFilter { contact_identifiers: vec![bob], ..Default::default() };
+        assert_eq!(ids(&list(&filter, None)), vec![4, 3]);
+    }
+
+    #[test]
+    fn conversations_are_named_like_the_conversation_list() {
+        let page = list(&AttachmentFilter::default(), None);
+        let names: Vec<Option<&str>> = page.attachments.iter().map(|a| a.conversation_name.as_deref()).collect();
+        assert_eq!(names, vec![Some("Bob"), Some("Trip"), Some("Ann"), Some("Ann")]);
+        let senders: Vec<Option<&str>> = page.attachments.iter().map(|a| a.sender_name.as_deref()).collect();
+        assert_eq!(senders, vec![None, Some("Bob"), None, Some("Ann")]);
+    }
+
+    #[test]
+    fn pages_continue_after_the_cursor() {
+        let filter = AttachmentFilter { sort: GallerySort::Size, limit: Some(3), ..Default::default() };
+        let first = list(&filter, None);
+        assert_eq!(ids(&first), vec![4, 3, 2]);
+        assert_eq!(first.next_cursor.as_deref(), Some("20:2"));
+
+        let second = list(&filter, first.next_cursor.as_deref());
+        assert_eq!(ids(&second), vec![1]);
+        assert_eq!(second.next_cursor, None);
+        assert!(parse_cursor("20").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+// Reading NSKeyedArchiver plists, as stored in message payload_data.
+//
+// An archive is a flat "$objects" array in which objects refer to each other
+// by UID, with the root object named in "$top".
+use plist::Value;
+
+// Most NSString or NSURL wrappers followed to reach a string
+const MAX_STRING_DEPTH: usize = 8;
+
+pub struct KeyedArchive {
+    objects: Vec<Value>,
+    root: Value,
+}
+
+impl KeyedArchive {
+    /// Parses an archive, or returns None if `bytes` is not one.
+    pub fn parse(bytes: &[u8]) -> Option<KeyedArchive> {
+        let plist = Value::from_reader(std::io::Cursor::new(bytes)).ok()?;
+        let mut plist = plist.into_dictionary()?;
+        let objects = plist.remove("$objects")?.into_array()?;
+        let root = plist.get("$top")?.as_dictionary()?.get("root")?.clone();
+        Some(KeyedArchive { objects, root })
+    }
+
+    pub fn root(&self) -> &Value {
+        self.resolve(&self.root)
+    }
+
+    pub fn resolve<'a>(&'a self, value: &'a Value) -> &'a Value {
+        match value {
+            Value::Uid(uid) => self.objects.get(uid.get() as usize).unwrap_or(value),
+            _ => value,
+        }
+    }
+
+    pub fn get<'a>(&'a self, object: &'a Value, key: &str) -> Option<&'a Value> {
+        object.as_dictionary()?.get(key).map(|value| self.resolve(value))
+    }
+
+    /// Strings are stored plainly, as NSString or NSMutableString with
+    /// "NS.string", or as NSURL with "NS.relative". "$null" means no value.
+    pub fn string(&self, value: &Value) -> Option<String> {
+        // Archives come from other devices, so a wrapper pointing back at
+        // itself must not loop forever
+        let mut value = self.resolve(value);
+        for _ in 0..MAX_STRING_DEPTH {
+            match value {
+                Value::String(text) => {
+                    return Some(text.clone()).filter(|text| !text.is_empty() && text != "$null");
+                }
+                Value::Dictionary(_) => {
+                    value = self.get(value, "NS.string").or_else(|| self.get(value, "NS.relative"))?;
+                }
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    pub fn field(&self, object: &Value, key: &str) -> Option<String> {
+        self.get(object, key).and_then(|value| self.string(value))
+    }
+}
+
+/// Serializes `objects` as an archive whose root is object 1, for tests.
+#[cfg(test)]
+pub fn encode(objects: Vec<Value>) -> Vec<u8> {
+    let mut top = plist::Dictionary::new();
+    top.insert("root".to_string(), Value::Uid(plist::Uid::new(1)));
+    let mut archive = plist::Dictionary::new();
+    archive.insert("$archiver".to_string(), Value::String("NSKeyedArchiver".to_string()));
+    archive.insert("$top".to_string(), Value::Dictionary(top));
+    archive.insert("$objects".to_string(), Value::Array(objects));
+
+    let mut bytes = Vec::new();
+    Value::Dictionary(archive).to_writer_binary(&mut bytes).unwrap();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uid(index: u64) -> Value {
+        Value::Uid(plist::Uid::new(index))
+    }
+
+    fn object(entries: &[(&str, Value)]) -> Value {
+        Value::Dictionary(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())
+    }
+
+    #[test]
+    fn strings_unwrap_nested_objects() {
+        let bytes = encode(vec![
+            Value::String("$null".to_string()),
+            object(&[("NS.relative", uid(2)), ("empty", uid(0))]),
+            object(&[("NS.string", uid(3))]),
+            Value::String("https://example.com".to_string()),
+        ]);
+        let archive = KeyedArchive::parse(&bytes).unwrap();
+        assert_eq!(archive.string(archive.root()).as_deref(), Some("https://example.com"));
+        assert_eq!(archive.field(archive.root(), "empty"), None);
+    }
+
+    #[test]
+    fn self_referencing_strings_are_none() {
+        let bytes = encode(vec![
+            Value::String("$null".to_string()),
+            object(&[("NS.string", uid(2))]),
+            object(&[("NS.string", uid(2))]),
+        ]);
+        let archive = KeyedArchive::parse(&bytes).unwrap();
+        assert_eq!(archive.string(archive.root()), None);
+    }
+}
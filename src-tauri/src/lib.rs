@@ -2,6 +2,7 @@
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::OnceLock;
 use std::fs;
 use std::fmt;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
@@ -17,31 +18,167 @@ use reqwest;
 use scraper;
 use url;
 
+mod aliases;
+mod app_db;
+mod attachment_data;
+mod attachments;
+mod attributed_body;
+mod bookmarks;
+mod chat_meta;
+mod contacts;
+mod export;
+mod gallery;
+mod keyed_archive;
+mod link_preview;
+mod merge;
+mod message_kind;
+mod payments;
+mod query;
+mod reminders;
+mod saved_searches;
+mod search_history;
+mod search_jobs;
+mod suggest;
+mod text;
+mod thumbnails;
+mod unanswered;
+mod vocabulary;
+
+use search_jobs::SearchJobs;
+use suggest::SuggestIndexState;
+use tauri::{Emitter, Manager};
+use vocabulary::VocabularyState;
+
 // Define structs for our data
-#[derive(Serialize, Deserialize, Debug)]
+// Delivery and read receipts. For the user's messages these describe the
+// recipient; for incoming messages, is_read and date_read are the user's own.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DeliveryStatus {
+    is_sent: bool,
+    is_delivered: bool,
+    is_read: bool,
+    date_delivered: Option<i64>, // Unix seconds
+    date_read: Option<i64>,
+    error: i64,                  // chat.db error code, 0 if none
+}
+
+// Columns read by DeliveryStatus::from_row, in order
+const DELIVERY_STATUS_COLUMNS: &str = "m.is_sent, m.is_delivered, m.is_read, m.date_delivered, m.date_read, m.error";
+
+impl DeliveryStatus {
+    const COLUMN_COUNT: usize = 6;
+
+    fn from_row(row: &rusqlite::Row, first: usize) -> DeliveryStatus {
+        let flag = |i: usize| row.get::<_, Option<i64>>(first + i).ok().flatten().unwrap_or(0) == 1;
+        let date = |i: usize| {
+            row.get::<_, Option<i64>>(first + i)
+                .ok()
+                .flatten()
+                .filter(|date| *date > 0)
+                .map(|date| apple_time_to_unix(date / 1_000_000_000))
+        };
+        DeliveryStatus {
+            is_sent: flag(0),
+            is_delivered: flag(1),
+            is_read: flag(2),
+            date_delivered: date(3),
+            date_read: date(4),
+            error: row.get::<_, Option<i64>>(first + 5).ok().flatten().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Conversation {
     id: String,
+    guid: String,
     name: Option<String>,
     last_message: Option<String>,
     last_message_date: i64,
+    // Pinned, hidden, color and labels set in the app
+    #[serde(flatten)]
+    meta: chat_meta::ChatMeta,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Message {
     id: i64,
+    guid: String,
     text: String,
     date: i64,
     is_from_me: bool,
     chat_id: Option<String>,
     sender_name: Option<String>,
+    // Raw handle of the sender; sender_name may be a local alias
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender_handle: Option<String>,
+    // "iMessage", "SMS" or "RCS"
+    service: Option<String>,
+    #[serde(flatten)]
+    status: DeliveryStatus,
+    // Audio, sticker, app or effect message, with their details
+    #[serde(flatten)]
+    kind: message_kind::MessageKind,
+    // Preview archived with a link message, when Messages stored one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link_preview: Option<OpenGraphData>,
     attachment_path: Option<String>,
     attachment_mime_type: Option<String>,
+    // attachment:// URL of the file, when it is below the attachments root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment_state: Option<attachments::AttachmentState>,
+    // Contact card, location or invite parsed from the attachment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment_data: Option<attachment_data::AttachmentData>,
     conversation_name: Option<String>,
+    // Search only: excerpt around the first match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+    // Search only: matched ranges of `text` as UTF-16 offsets
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    highlights: Vec<(usize, usize)>,
+    // Search only: neighbouring messages from the same chat
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    context_before: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    context_after: Vec<Message>,
+    // Handles @-mentioned in the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mentioned_handles: Vec<String>,
+    // Star, note and tags from the app database
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bookmark: Option<bookmarks::Bookmark>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SearchResult {
+    #[serde(default)]
+    search_id: u64,
     messages: Vec<Message>,
+    #[serde(default)]
+    suggestions: Vec<String>,
+    #[serde(default)]
+    facets: Option<SearchFacets>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FacetCount {
+    key: String,
+    label: Option<String>,
+    count: i64,
+}
+
+// Aggregates over every message matching a search, not just the returned page
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SearchFacets {
+    total_hits: i64,
+    conversations: Vec<FacetCount>,
+    senders: Vec<FacetCount>,       // "me" for my own messages
+    months: Vec<FacetCount>,        // "yyyy-MM" in local time, oldest first
+    attachment_types: Vec<FacetCount>,
+    chat_labels: Vec<FacetCount>,   // labels set on the matching chats
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -126,6 +263,8 @@ pub enum AppError {
     IOError(std::io::Error),
     SerializationError(serde_json::Error),
     PermissionError(String),
+    RegexError(regex::Error),
+    SearchCancelled(u64),
     OtherError(String),
 }
 
@@ -138,6 +277,8 @@ impl fmt::Display for AppError {
             AppError::IOError(e) => write!(f, "IO error: {}", e),
             AppError::SerializationError(e) => write!(f, "Serialization error: {}", e),
             AppError::PermissionError(s) => write!(f, "Permission error: {}", s),
+            AppError::RegexError(e) => write!(f, "Invalid regular expression: {}", e),
+            AppError::SearchCancelled(id) => write!(f, "Search {} was cancelled", id),
             AppError::OtherError(s) => write!(f, "Other error: {}", s),
         }
     }
@@ -161,6 +302,12 @@ impl From<serde_json::Error> for AppError {
     }
 }
 
+impl From<regex::Error> for AppError {
+    fn from(error: regex::Error) -> Self {
+        AppError::RegexError(error)
+    }
+}
+
 impl serde::Serialize for AppError {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -232,6 +379,15 @@ fn get_imessage_db_path() -> Result<PathBuf, AppError> {
     }
 }
 
+// Open chat.db with the custom search functions registered
+fn open_imessage_db() -> Result<Connection, AppError> {
+    let db_path = get_imessage_db_path()?;
+    let conn = Connection::open(&db_path).map_err(AppError::DatabaseConnectionError)?;
+    text::register_functions(&conn).map_err(AppError::DatabaseConnectionError)?;
+    attributed_body::register_functions(&conn).map_err(AppError::DatabaseConnectionError)?;
+    Ok(conn)
+}
+
 fn apple_time_to_unix(apple_time: i64) -> i64 {
     // Apple uses Jan 1, 2001 as its epoch
     // Unix epoch is Jan 1, 1970
@@ -632,28 +788,45 @@ async fn read_contacts() -> Result<ContactResponse, AppError> {
     })
 }
 
-// Tauri commands
-#[tauri::command]
-async fn get_conversations() -> Result<Vec<Conversation>, AppError> {
-    let db_path = match get_imessage_db_path() {
-        Ok(path) => path,
-        Err(e) => return Err(e),
-    };
-    
-    let conn = match Connection::open(&db_path) {
-        Ok(conn) => conn,
-        Err(e) => return Err(AppError::DatabaseConnectionError(e)),
+// Name shown for a conversation: its own name, otherwise the local name or the
+// handle of the other participant
+fn conversation_name(
+    display_name: Option<String>,
+    handle: Option<String>,
+    aliases: &aliases::Aliases,
+) -> Option<String> {
+    match (display_name.filter(|name| !name.is_empty()), handle) {
+        (Some(name), _) => Some(name),
+        (None, Some(handle)) => Some(aliases.display_name(&handle).map(str::to_string).unwrap_or(handle)),
+        (None, None) => None,
+    }
+}
+
+// The 100 most recent chats with their app metadata. Without the attached app
+// database nothing is pinned or hidden, and a label matches no chat.
+fn conversations_query(app_attached: bool) -> String {
+    let (meta_columns, meta_join, meta_filter) = if app_attached {
+        (
+            "COALESCE(cm.pinned, 0) as pinned, COALESCE(cm.hidden, 0) as hidden, cm.color",
+            "LEFT JOIN app.chat_meta cm ON cm.chat_guid = c.guid",
+            "(?1 OR COALESCE(cm.hidden, 0) = 0)
+            AND (?2 IS NULL OR c.guid IN (SELECT chat_guid FROM app.chat_label WHERE label = ?2))",
+        )
+    } else {
+        ("0 as pinned, 0 as hidden, NULL as color", "", "(?1 OR 1) AND ?2 IS NULL")
     };
-    
-    let query = r#"
+    format!(r#"
         SELECT 
             c.ROWID as chat_id, 
             c.display_name,
             h.id as handle_id,
             m.text as last_message,
-            MAX(m.date) as last_message_date
+            MAX(m.date) as last_message_date,
+            c.guid,
+            {}
         FROM 
             chat c
+        {}
         LEFT JOIN 
             chat_handle_join chj ON c.ROWID = chj.chat_id
         LEFT JOIN 
@@ -662,16 +835,52 @@ async fn get_conversations() -> Result<Vec<Conversation>, AppError> {
             chat_message_join cmj ON c.ROWID = cmj.chat_id
         LEFT JOIN 
             message m ON cmj.message_id = m.ROWID
+        WHERE
+            {}
         GROUP BY 
             c.ROWID
         ORDER BY 
-            last_message_date DESC
+            pinned DESC, last_message_date DESC
         LIMIT 100
-    "#;
+    "#, meta_columns, meta_join, meta_filter)
+}
+
+// Tauri commands
+// Pinned chats come first. Hidden chats are left out unless include_hidden is set,
+// and `label` limits the list to chats with that label.
+#[tauri::command]
+async fn get_conversations(
+    include_hidden: Option<bool>,
+    label: Option<String>,
+    merged: Option<bool>,
+) -> Result<Vec<Conversation>, AppError> {
+    let db_path = match get_imessage_db_path() {
+        Ok(path) => path,
+        Err(e) => return Err(e),
+    };
+    
+    let conn = match Connection::open(&db_path) {
+        Ok(conn) => conn,
+        Err(e) => return Err(AppError::DatabaseConnectionError(e)),
+    };
+    // The chat list still loads, without pins, hiding or labels, when the
+    // app database can't be opened
+    let app_attached = app_db::attach(&conn)
+        .map_err(|e| log::warn!("Listing conversations without app data: {}", e))
+        .is_ok();
+    let labels = app_db::open_app_db()
+        .and_then(|app_conn| chat_meta::labels_by_chat(&app_conn))
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to read conversation labels: {}", e);
+            std::collections::HashMap::new()
+        });
+    let aliases = aliases::Aliases::load();
+    
+    let query = conversations_query(app_attached);
     
-    let mut stmt = conn.prepare(query)?;
+    let mut stmt = conn.prepare(&query)?;
     
-    let conversation_iter = stmt.query_map([], |row| {
+    let conversation_iter = stmt.query_map(rusqlite::params![include_hidden.unwrap_or(false), label], |row| {
         let chat_id: i64 = row.get(0)?;
         let display_name: Option<String> = row.get(1)?;
         let handle_id: Option<String> = row.get(2)?;
@@ -686,18 +895,24 @@ async fn get_conversations() -> Result<Vec<Conversation>, AppError> {
         
         // Clean up the display name or handle_id if it's a phone number
         // Use display name if available, otherwise use handle_id
-        let name = match (display_name, handle_id) {
-            (Some(dname), _) => Some(dname),
-            (None, Some(hid)) => Some(hid),
-            (None, None) => None,
-        };
+        let name = conversation_name(display_name, handle_id, &aliases);
             
         
+        let guid: String = row.get(5)?;
+        let meta = chat_meta::ChatMeta {
+            pinned: row.get(6)?,
+            hidden: row.get(7)?,
+            color: row.get(8)?,
+            labels: labels.get(&guid).cloned().unwrap_or_default(),
+        };
+        
         Ok(Conversation {
             id: chat_id.to_string(),
+            guid,
             name,
             last_message,
             last_message_date,
+            meta,
         })
     })?;
     
@@ -743,6 +958,7 @@ async fn get_conversations() -> Result<Vec<Conversation>, AppError> {
                 name,
                 last_message: None,
                 last_message_date: 0,
+                ..Default::default()
             })
         })?;
         
@@ -752,48 +968,28 @@ async fn get_conversations() -> Result<Vec<Conversation>, AppError> {
             }
         }
     }
+
+    // Optionally merge chats with the same people across services
+    if merged.unwrap_or(false) {
+        return merge::merge_conversations(&conn, conversations);
+    }
     
     Ok(conversations)
 }
 
-// Fix the get_message_attachments function
-fn get_message_attachments(conn: &Connection, message_id: i64) -> Result<(Option<String>, Option<String>), rusqlite::Error> {
-    let mut stmt = conn.prepare(r#"
-        SELECT 
-            a.filename,
-            a.mime_type
-        FROM 
-            attachment a
-        JOIN 
-            message_attachment_join maj ON maj.attachment_id = a.ROWID
-        WHERE 
-            maj.message_id = ?
-        LIMIT 1
-    "#)?;
-
-    stmt.query_row([message_id], |row| {
-        Ok((row.get::<_, String>(0).optional()?, row.get::<_, String>(1).optional()?))
-    }).optional().map(|opt| opt.unwrap_or((None, None)))
-}
-
 #[tauri::command]
 async fn get_messages(conversation_id: String) -> Result<Vec<Message>, AppError> {
     
     let db_path = get_imessage_db_path()?;
     let conn = Connection::open(&db_path).map_err(AppError::DatabaseConnectionError)?;
     
-    let chat_id: i64 = conversation_id.parse().map_err(|_| AppError::OtherError("Invalid conversation ID".to_string()))?;
+    // Merged conversations list several chat ids
+    let chat_ids = merge::parse_chat_ids(&conversation_id)?;
+    let chat_ids: Vec<String> = chat_ids.iter().map(|id| id.to_string()).collect();
     
-    // Updated query to include conversation name
-    let mut stmt = conn.prepare(r#"
+    let mut stmt = conn.prepare(&format!(r#"
         SELECT 
-            m.ROWID as message_id,
-            m.text,
-            m.date,
-            m.is_from_me,
-            h.id as handle_id,
-            COALESCE(h.uncanonicalized_id, h.id) as sender_id,
-            c.display_name as conversation_name
+            {}, c.display_name as conversation_name
         FROM 
             message m
         INNER JOIN 
@@ -803,58 +999,13 @@ async fn get_messages(conversation_id: String) -> Result<Vec<Message>, AppError>
         LEFT JOIN
             handle h ON m.handle_id = h.ROWID
         WHERE 
-            cmj.chat_id = ?
+            cmj.chat_id IN ({})
         ORDER BY 
             m.date ASC
         LIMIT 1000
-    "#)?;
+    "#, message_columns(), chat_ids.join(", ")))?;
     
-    let message_iter = stmt.query_map([chat_id], |row| {
-        let message_id: i64 = row.get(0)?;
-        let text: Option<String> = row.get(1)?;
-        
-        // Handle potential NULL or type issues with date
-        let date: Result<i64, rusqlite::Error> = row.get(2);
-        let date = match date {
-            Ok(date) => apple_time_to_unix(date / 1_000_000_000),
-            Err(_) => 0, // Default to 0 for NULL dates
-        };
-        
-        // Handle potential issues with is_from_me
-        let is_from_me: Result<i64, rusqlite::Error> = row.get(3);
-        let is_from_me = match is_from_me {
-            Ok(value) => value == 1,
-            Err(_) => false, // Default to false for NULL or invalid values
-        };
-        
-        // Get sender information
-        let sender_id: Result<String, rusqlite::Error> = row.get(5);
-        let sender_name = match sender_id {
-            Ok(id) if !is_from_me => Some(id),
-            _ => None,
-        };
-
-        // Get conversation name
-        let conversation_name: Option<String> = row.get(6)?;
-
-        // Get attachment path and mime type
-        let (attachment_path, attachment_mime_type) = match get_message_attachments(&conn, message_id) {
-            Ok((path, mime)) => (path, mime),
-            Err(_) => (None, None),
-        };
-        
-        Ok(Message {
-            id: message_id,
-            text: text.unwrap_or_else(|| "[Attachment or empty message]".to_string()),
-            date,
-            is_from_me,
-            chat_id: Some(conversation_id.clone()),
-            sender_name,
-            attachment_path,
-            attachment_mime_type,
-            conversation_name,
-        })
-    })?;
+    let message_iter = stmt.query_map([], message_from_row)?;
     
     let mut messages = Vec::new();
     for message in message_iter {
@@ -864,6 +1015,7 @@ async fn get_messages(conversation_id: String) -> Result<Vec<Message>, AppError>
         }
     }
     
+    annotate_messages(&mut messages);
     Ok(messages)
 }
 
@@ -878,14 +1030,43 @@ impl rusqlite::ToSql for SqlParam {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct ContactIdentifier {
     contact_id: Option<String>,
     phones: Vec<String>,
     emails: Vec<String>,
+    // Local person whose linked handles are matched as well
+    #[serde(default)]
+    person_id: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum MatchMode {
+    #[default]
+    Substring,
+    Word,
+    Regex,
+    Fuzzy,
+}
+
+// Whose @-mentions to search for
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum MentionFilter {
+    Me,
+    Contact(ContactIdentifier),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    #[default]
+    Date,
+    Relevance,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct SearchParams {
     query: String,
     start_date: Option<String>,  // yyyy-MM-dd format
@@ -898,6 +1079,32 @@ struct SearchParams {
     sort_direction: String,      // "asc" or "desc"
     conversation_type: String,   // "all", "direct", or "group"
     attachment_type: String,     // "all", "image", "video", "pdf", "audio", "other"
+    #[serde(default)]
+    match_mode: MatchMode,       // "substring", "word", "regex" or "fuzzy"
+    #[serde(default)]
+    sort: SortOrder,             // "date" (uses sort_direction) or "relevance"
+    #[serde(default)]
+    context_before: usize,       // neighbouring messages returned with each hit
+    #[serde(default)]
+    context_after: usize,
+    #[serde(default)]
+    include_facets: bool,        // compute SearchFacets over the full match set
+    #[serde(default)]
+    mentions: Option<MentionFilter>, // {"kind": "me"} or {"kind": "contact", ...ContactIdentifier}
+    #[serde(default)]
+    bookmarked_only: bool,       // only starred, noted or tagged messages
+    #[serde(default)]
+    bookmark_tags: Vec<String>,  // bookmarks with any of these tags
+    #[serde(default)]
+    chat_labels: Vec<String>,    // only chats with any of these labels
+    #[serde(default)]
+    failed_only: bool,           // only messages that failed to send
+    #[serde(default)]
+    sms_only: bool,              // only messages sent over SMS
+    #[serde(default)]
+    read_unanswered: bool,       // my messages they read without replying
+    #[serde(default)]
+    kinds: Vec<String>,          // any of "text", "audio", "sticker", "app", "effect"
 }
 
 // Add this helper function at the top level, before search_messages
@@ -905,15 +1112,63 @@ fn normalize_phone_number(phone: &str) -> String {
     phone.chars().filter(|c| c.is_ascii_digit()).collect()
 }
 
-#[tauri::command]
-async fn search_messages(params: SearchParams) -> Result<SearchResult, AppError> {
-    println!("Received search params: {:?}", params);
-    
-    let db_path = get_imessage_db_path()?;
-    let conn = Connection::open(&db_path).map_err(AppError::DatabaseConnectionError)?;
+// Normalizes a handle so chat.db and AddressBook spellings compare equal
+fn handle_key(handle: &str) -> String {
+    if handle.contains('@') {
+        handle.to_lowercase()
+    } else {
+        let digits = normalize_phone_number(handle);
+        digits[digits.len().saturating_sub(10)..].to_string()
+    }
+}
 
-    let mut sql = r#"
-        SELECT DISTINCT
+// ROWIDs of the chat.db handles whose handle_key is one of `keys`
+fn handle_rowids(conn: &Connection, keys: &[String]) -> Result<Vec<i64>, AppError> {
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+    let keys: std::collections::HashSet<&str> = keys.iter().map(String::as_str).collect();
+    let mut stmt = conn.prepare("SELECT ROWID, id FROM handle")?;
+    let handles = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(handles
+        .into_iter()
+        .filter(|(_, id)| keys.contains(handle_key(id).as_str()))
+        .map(|(rowid, _)| rowid)
+        .collect())
+}
+
+// Handles the user sends from, taken from the accounts recorded on messages
+fn my_handles(conn: &Connection) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT account FROM message WHERE account IS NOT NULL AND account != ''",
+    )?;
+    let accounts = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Accounts look like "e:me@icloud.com" or "p:+15551234567"
+    let mut handles: Vec<String> = accounts
+        .iter()
+        .map(|account| match account.split_once(':') {
+            Some((_, handle)) => handle.to_string(),
+            None => account.clone(),
+        })
+        .collect();
+    handles.sort();
+    handles.dedup();
+    Ok(handles)
+}
+
+// Columns read by message_from_row. Queries using them join message m,
+// chat_message_join cmj and handle h, and may add c.display_name as column
+// CONVERSATION_NAME_COLUMN.
+fn message_columns() -> &'static str {
+    static COLUMNS: OnceLock<String> = OnceLock::new();
+    COLUMNS.get_or_init(|| {
+        format!(
+            r#"
             m.ROWID as message_id,
             m.text,
             m.date,
@@ -934,7 +1189,242 @@ async fn search_messages(params: SearchParams) -> Result<SearchResult, AppError>
                 JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID 
                 WHERE maj.message_id = m.ROWID 
                 LIMIT 1
-            ) as attachment_mime_type
+            ) as attachment_mime_type,
+            m.attributedBody,
+            m.guid,
+            m.service,
+            {},
+            {},
+            {}"#,
+            DELIVERY_STATUS_COLUMNS,
+            message_kind::message_kind_columns(),
+            link_preview::LINK_PAYLOAD_COLUMN
+        )
+    })
+}
+
+// Positions in message_columns() of the column groups read by their own row mappers
+const STATUS_FIRST_COLUMN: usize = 12;
+const KIND_FIRST_COLUMN: usize = STATUS_FIRST_COLUMN + DeliveryStatus::COLUMN_COUNT;
+const LINK_PAYLOAD_COLUMN: usize = KIND_FIRST_COLUMN + message_kind::MessageKind::COLUMN_COUNT;
+const CONVERSATION_NAME_COLUMN: usize = LINK_PAYLOAD_COLUMN + 1;
+
+fn message_from_row(row: &rusqlite::Row) -> rusqlite::Result<Message> {
+    let message_id: i64 = row.get(0)?;
+    let text: Option<String> = row.get(1)?;
+    
+    let date: Result<i64, rusqlite::Error> = row.get(2);
+    let date = match date {
+        Ok(date) => apple_time_to_unix(date / 1_000_000_000),
+        Err(_) => 0,
+    };
+    
+    let is_from_me: Result<i64, rusqlite::Error> = row.get(3);
+    let is_from_me = match is_from_me {
+        Ok(value) => value == 1,
+        Err(_) => false,
+    };
+
+    let chat_id: Result<i64, rusqlite::Error> = row.get(4);
+    let chat_id = match chat_id {
+        Ok(id) => Some(id.to_string()),
+        Err(_) => None,
+    };
+    
+    let sender_id: Result<String, rusqlite::Error> = row.get(6);
+    let sender_name = match sender_id {
+        Ok(id) if !is_from_me => Some(id),
+        _ => None,
+    };
+    let sender_handle = sender_name.clone();
+
+    let attachment_path: Option<String> = row.get(7).ok();
+    let attachment_mime_type: Option<String> = row.get(8).ok();
+    let attributed_body: Option<Vec<u8>> = row.get(9).ok().flatten();
+    let guid: Option<String> = row.get(10).ok().flatten();
+    let service: Option<String> = row.get(11).ok().flatten();
+    let status = DeliveryStatus::from_row(row, STATUS_FIRST_COLUMN);
+    let kind = message_kind::MessageKind::from_row(row, KIND_FIRST_COLUMN);
+    let link_payload: Option<Vec<u8>> = row.get(LINK_PAYLOAD_COLUMN).ok().flatten();
+    let conversation_name: Option<String> = row.get(CONVERSATION_NAME_COLUMN).ok().flatten();
+    
+    Ok(Message {
+        id: message_id,
+        guid: guid.unwrap_or_default(),
+        text: text.unwrap_or_else(|| "[Attachment or empty message]".to_string()),
+        date,
+        is_from_me,
+        chat_id,
+        sender_name,
+        sender_handle,
+        service,
+        status,
+        kind,
+        link_preview: link_payload.and_then(|payload| link_preview::decode(&payload)),
+        attachment_path,
+        attachment_mime_type,
+        conversation_name,
+        mentioned_handles: attributed_body
+            .map(|body| attributed_body::mentioned_handles(&body))
+            .unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+// Upper bound for context requested around a single message
+const MAX_CONTEXT_MESSAGES: usize = 50;
+
+// Load up to `before` and `after` messages around a message in the same chat.
+// Messages are ordered by date, with ROWID breaking ties between equal dates.
+fn messages_around(
+    conn: &Connection,
+    chat_id: i64,
+    message_id: i64,
+    before: usize,
+    after: usize,
+) -> Result<(Vec<Message>, Vec<Message>), AppError> {
+    let date: i64 = conn.query_row(
+        "SELECT date FROM message WHERE ROWID = ?",
+        [message_id],
+        |row| row.get(0),
+    )?;
+
+    let load = |condition: &str, order: &str, limit: usize| -> Result<Vec<Message>, AppError> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let sql = format!(r#"
+            SELECT
+                {}, c.display_name as conversation_name
+            FROM 
+                message m
+            INNER JOIN 
+                chat_message_join cmj ON m.ROWID = cmj.message_id
+            INNER JOIN
+                chat c ON cmj.chat_id = c.ROWID
+            LEFT JOIN
+                handle h ON m.handle_id = h.ROWID
+            WHERE 
+                cmj.chat_id = ?1 AND ({})
+            ORDER BY 
+                m.date {order}, m.ROWID {order}
+            LIMIT ?4
+        "#, message_columns(), condition, order = order);
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            rusqlite::params![chat_id, date, message_id, limit.min(MAX_CONTEXT_MESSAGES) as i64],
+            message_from_row,
+        )?;
+        Ok(rows.filter_map(Result::ok).collect())
+    };
+
+    let mut earlier = load("m.date < ?2 OR (m.date = ?2 AND m.ROWID < ?3)", "DESC", before)?;
+    earlier.reverse();
+    let later = load("m.date > ?2 OR (m.date = ?2 AND m.ROWID > ?3)", "ASC", after)?;
+    Ok((earlier, later))
+}
+
+// Adds data from outside the message row: bookmarks, local sender names,
+// resolved attachment files and parsed contact, location and invite attachments
+fn annotate_messages(messages: &mut [Message]) {
+    bookmarks::annotate(messages);
+    attachments::annotate(messages);
+    let aliases = aliases::Aliases::load();
+    for message in messages {
+        aliases.apply(message);
+        if let Some(path) = &message.attachment_path {
+            message.attachment_data = attachment_data::parse(path, message.attachment_mime_type.as_deref());
+        }
+    }
+}
+
+// Find a message by its guid, which stays stable when chat.db is rebuilt
+fn message_by_guid(conn: &Connection, guid: &str) -> Result<Option<Message>, AppError> {
+    let sql = format!(r#"
+        SELECT
+            {}, c.display_name as conversation_name
+        FROM 
+            message m
+        INNER JOIN 
+            chat_message_join cmj ON m.ROWID = cmj.message_id
+        INNER JOIN
+            chat c ON cmj.chat_id = c.ROWID
+        LEFT JOIN
+            handle h ON m.handle_id = h.ROWID
+        WHERE 
+            m.guid = ?
+        LIMIT 1
+    "#, message_columns());
+    Ok(conn.query_row(&sql, [guid], message_from_row).optional()?)
+}
+
+// Load a message together with its neighbours, so a search hit can be opened in place
+#[tauri::command]
+async fn get_messages_around(
+    chat_id: String,
+    message_id: i64,
+    before: usize,
+    after: usize,
+) -> Result<Vec<Message>, AppError> {
+    let conn = open_imessage_db()?;
+    let chat_id: i64 = chat_id.parse().map_err(|_| AppError::OtherError("Invalid conversation ID".to_string()))?;
+
+    let sql = format!(r#"
+        SELECT
+            {}, c.display_name as conversation_name
+        FROM 
+            message m
+        INNER JOIN 
+            chat_message_join cmj ON m.ROWID = cmj.message_id
+        INNER JOIN
+            chat c ON cmj.chat_id = c.ROWID
+        LEFT JOIN
+            handle h ON m.handle_id = h.ROWID
+        WHERE 
+            cmj.chat_id = ? AND m.ROWID = ?
+    "#, message_columns());
+    let target = conn
+        .query_row(&sql, [chat_id, message_id], message_from_row)
+        .optional()?
+        .ok_or_else(|| AppError::OtherError(format!("Message {} not found in conversation {}", message_id, chat_id)))?;
+
+    let (earlier, later) = messages_around(&conn, chat_id, message_id, before, after)?;
+
+    let mut messages = earlier;
+    messages.push(target);
+    messages.extend(later);
+    annotate_messages(&mut messages);
+    Ok(messages)
+}
+
+const SEARCH_LIMIT: usize = 100;
+// Characters of context kept either side of the first match in a snippet
+const SNIPPET_CONTEXT_CHARS: usize = 60;
+// Vocabulary words tried for each word of a fuzzy query
+const FUZZY_TERMS_PER_WORD: usize = 20;
+// Exact searches returning fewer hits than this get "did you mean" suggestions
+const SUGGESTION_THRESHOLD: usize = 5;
+const MAX_SUGGESTIONS: usize = 3;
+
+// Sum over the query words of the smallest edit distance of a variant found in the text
+fn fuzzy_score(text: &str, fuzzy_terms: &[Vec<(String, usize)>]) -> usize {
+    let folded = text::fold_text(text);
+    fuzzy_terms
+        .iter()
+        .map(|terms| {
+            terms
+                .iter()
+                .filter(|(term, _)| folded.contains(term.as_str()))
+                .map(|(_, distance)| *distance)
+                .min()
+                .unwrap_or(usize::MAX / fuzzy_terms.len())
+        })
+        .sum()
+}
+
+// Tables every search query reads from; filters are appended after WHERE 1=1
+const SEARCH_FROM: &str = r#"
         FROM 
             message m
         INNER JOIN 
@@ -942,83 +1432,198 @@ async fn search_messages(params: SearchParams) -> Result<SearchResult, AppError>
         LEFT JOIN
             handle h ON m.handle_id = h.ROWID
         WHERE 1=1
-    "#.to_string();
+    "#;
 
-    // Add text search if query is not empty
-    if !params.query.trim().is_empty() {
-        sql.push_str(&format!(" AND m.text LIKE '%{}%'", params.query.replace('\'', "''")));
-    }
+// Maps attachment a to the categories used by the attachment_type filter
+const ATTACHMENT_CATEGORY_SQL: &str = r#"CASE 
+                WHEN a.mime_type LIKE 'image/%' THEN 'image'
+                WHEN a.mime_type LIKE 'video/%' THEN 'video'
+                WHEN a.mime_type = 'application/pdf' THEN 'pdf'
+                WHEN a.mime_type LIKE 'audio/%' THEN 'audio'
+                ELSE 'other'
+            END"#;
 
-    // Add contact identifier filters if any exist
-    if !params.contact_identifiers.is_empty() {
-        let mut conditions = Vec::new();
+// Conditions on message m used by both the filter checkboxes and has: operators
+const HAS_LINK_SQL: &str = "(instr(m.text, 'http://') > 0 OR instr(m.text, 'https://') > 0)";
+
+const HAS_ATTACHMENT_SQL: &str = r#"EXISTS (
+            SELECT 1 
+            FROM attachment a 
+            JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID 
+            WHERE maj.message_id = m.ROWID
+        )"#;
+
+// Takes the category as a bound parameter
+fn has_attachment_category_sql() -> String {
+    format!(r#"EXISTS (
+            SELECT 1 
+            FROM attachment a 
+            JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID 
+            WHERE maj.message_id = m.ROWID
+            AND {} = ?
+        )"#, ATTACHMENT_CATEGORY_SQL)
+}
+
+// Condition on handle h matching any of the identifiers, each expanded to the
+// handles of its linked person
+fn contact_identifiers_sql(identifiers: &[ContactIdentifier], aliases: &aliases::Aliases) -> Option<String> {
+    if identifiers.is_empty() {
+        return None;
+    }
+    let mut conditions = Vec::new();
+    
+    for identifier in identifiers.iter().map(|i| aliases.expand(i)) {
+        let mut identifier_conditions = Vec::new();
         
-        for identifier in &params.contact_identifiers {
-            let mut identifier_conditions = Vec::new();
-            
-            // Add contact_id condition if it exists
-            if let Some(contact_id) = &identifier.contact_id {
-                let escaped_id = contact_id.replace('\'', "''");
-                identifier_conditions.push(format!(
-                    "(h.id = '{}' OR h.uncanonicalized_id = '{}')",
-                    escaped_id, escaped_id
-                ));
-            }
-            
-            // Add phone conditions with more flexible matching
-            for phone in &identifier.phones {
-                let numeric_phone = normalize_phone_number(phone);
-                if !numeric_phone.is_empty() {
-                    let last_10 = if numeric_phone.len() > 10 {
-                        numeric_phone[numeric_phone.len()-10..].to_string()
-                    } else {
-                        numeric_phone
-                    };
-                    
-                    identifier_conditions.push(format!(
-                        "(
-                            REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(h.id, '+', ''), '-', ''), ' ', ''), '(', ''), ')', '') LIKE '%{}' OR 
-                            REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(h.uncanonicalized_id, '+', ''), '-', ''), ' ', ''), '(', ''), ')', '') LIKE '%{}'
-                        )",
-                        last_10, last_10
-                    ));
-                }
-            }
-            
-            // Add email conditions (keep as is since emails are exact matches)
-            for email in &identifier.emails {
-                let escaped_email = email.replace('\'', "''");
+        // Add contact_id condition if it exists
+        if let Some(contact_id) = &identifier.contact_id {
+            let escaped_id = contact_id.replace('\'', "''");
+            identifier_conditions.push(format!(
+                "(h.id = '{}' OR h.uncanonicalized_id = '{}')",
+                escaped_id, escaped_id
+            ));
+        }
+        
+        // Add phone conditions with more flexible matching
+        for phone in &identifier.phones {
+            let numeric_phone = normalize_phone_number(phone);
+            if !numeric_phone.is_empty() {
+                let last_10 = if numeric_phone.len() > 10 {
+                    numeric_phone[numeric_phone.len()-10..].to_string()
+                } else {
+                    numeric_phone
+                };
+                
                 identifier_conditions.push(format!(
-                    "(h.id = '{}' OR h.uncanonicalized_id = '{}')",
-                    escaped_email, escaped_email
+                    "(
+                        REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(h.id, '+', ''), '-', ''), ' ', ''), '(', ''), ')', '') LIKE '%{}' OR 
+                        REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(h.uncanonicalized_id, '+', ''), '-', ''), ' ', ''), '(', ''), ')', '') LIKE '%{}'
+                    )",
+                    last_10, last_10
                 ));
             }
-            
-            if !identifier_conditions.is_empty() {
-                conditions.push(format!("({})", identifier_conditions.join(" OR ")));
-            }
         }
         
-        if !conditions.is_empty() {
-            sql.push_str(" AND (");
-            sql.push_str(&conditions.join(" OR "));
-            sql.push_str(")");
+        // Add email conditions (keep as is since emails are exact matches)
+        for email in &identifier.emails {
+            let escaped_email = email.replace('\'', "''");
+            identifier_conditions.push(format!(
+                "(h.id = '{}' OR h.uncanonicalized_id = '{}')",
+                escaped_email, escaped_email
+            ));
+        }
+        
+        if !identifier_conditions.is_empty() {
+            conditions.push(format!("({})", identifier_conditions.join(" OR ")));
         }
     }
+    
+    if conditions.is_empty() {
+        None
+    } else {
+        Some(format!("({})", conditions.join(" OR ")))
+    }
+}
+
+// SQL conditions and bound values for a search, shared by the result and facet queries
+struct SearchFilters {
+    // Conditions appended after SEARCH_FROM
+    sql: String,
+    params: Vec<Box<dyn rusqlite::ToSql>>,
+    folded_query: String,
+    // For fuzzy searches, the accepted variants of each query word and their edit distance
+    fuzzy_terms: Vec<Vec<(String, usize)>>,
+    highlighter: Option<text::Highlighter>,
+}
+
+fn build_search_filters(
+    conn: &Connection,
+    params: &SearchParams,
+    vocabulary: &VocabularyState,
+) -> Result<SearchFilters, AppError> {
+    // Parameters are pushed in the same order as their placeholders
+    let mut sql = String::new();
+    let mut query_params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+
+    // Take from:, has: and in: operators out of the query; regex patterns are left whole
+    let parsed = if params.match_mode == MatchMode::Regex {
+        query::ParsedQuery { text: params.query.clone(), ..Default::default() }
+    } else {
+        query::parse(&params.query)
+    };
+
+    // Add text search if query is not empty. Substring and word matches fold both
+    // sides so they ignore case and diacritics for any script, and instr keeps CJK
+    // substrings working. Regex patterns run against the original text.
+    let folded_query = text::fold_text(parsed.text.trim());
+    let mut fuzzy_terms: Vec<Vec<(String, usize)>> = Vec::new();
+    let mut highlighter = None;
+    if !folded_query.is_empty() {
+        match params.match_mode {
+            MatchMode::Substring => {
+                sql.push_str(" AND instr(fold(m.text), ?) > 0");
+                query_params.push(Box::new(folded_query.clone()));
+                highlighter = Some(text::Highlighter::Terms(vec![folded_query.clone()]));
+            },
+            MatchMode::Word => {
+                let pattern = text::whole_word_pattern(&folded_query);
+                highlighter = Some(text::Highlighter::FoldedPattern(regex::Regex::new(&pattern)?));
+                sql.push_str(" AND fold(m.text) REGEXP ?");
+                query_params.push(Box::new(pattern));
+            },
+            MatchMode::Regex => {
+                // Compile up front so an invalid pattern is reported instead of failing mid-query
+                highlighter = Some(text::Highlighter::Pattern(regex::Regex::new(&params.query)?));
+                sql.push_str(" AND m.text REGEXP ?");
+                query_params.push(Box::new(params.query.clone()));
+            },
+            MatchMode::Fuzzy => {
+                let mut vocabulary = vocabulary.0.lock().map_err(|e| AppError::OtherError(e.to_string()))?;
+                vocabulary.update(conn)?;
+
+                // Every query word must match one of its close vocabulary words
+                for word in text::words(&folded_query) {
+                    let mut terms: Vec<(String, usize)> = vocabulary
+                        .corrections(word, FUZZY_TERMS_PER_WORD)
+                        .into_iter()
+                        .map(|c| (c.word, c.distance))
+                        .collect();
+                    if !terms.iter().any(|(term, _)| term == word) {
+                        terms.push((word.to_string(), 0));
+                    }
+
+                    let conditions = vec!["instr(fold(m.text), ?) > 0"; terms.len()];
+                    sql.push_str(&format!(" AND ({})", conditions.join(" OR ")));
+                    for (term, _) in &terms {
+                        query_params.push(Box::new(term.clone()));
+                    }
+                    fuzzy_terms.push(terms);
+                }
+                let variants = fuzzy_terms.iter().flatten().map(|(term, _)| term.clone()).collect();
+                highlighter = Some(text::Highlighter::Terms(variants));
+            },
+        }
+    }
+
+    // Add contact identifier filters if any exist
+    if let Some(condition) = contact_identifiers_sql(&params.contact_identifiers, &aliases::Aliases::load()) {
+        sql.push_str(&format!(" AND {}", condition));
+    }
 
     // Add conversation filter if provided
-    if let Some(conv_id) = params.conversation_id {
-        sql.push_str(&format!(" AND cmj.chat_id = {}", conv_id));
+    if let Some(conv_id) = &params.conversation_id {
+        let chat_ids: Vec<String> = merge::parse_chat_ids(conv_id)?.iter().map(|id| id.to_string()).collect();
+        sql.push_str(&format!(" AND cmj.chat_id IN ({})", chat_ids.join(", ")));
     }
 
     // Add date filters
-    if let Some(start_date) = params.start_date {
-        if let Some(timestamp) = date_to_apple_timestamp(&start_date) {
+    if let Some(start_date) = &params.start_date {
+        if let Some(timestamp) = date_to_apple_timestamp(start_date) {
             sql.push_str(&format!(" AND m.date > {}", timestamp));
         }
     }
-    if let Some(end_date) = params.end_date {
-        if let Some(timestamp) = date_to_apple_timestamp(&end_date) {
+    if let Some(end_date) = &params.end_date {
+        if let Some(timestamp) = date_to_apple_timestamp(end_date) {
             sql.push_str(&format!(" AND m.date < {}", timestamp));
         }
     }
@@ -1030,34 +1635,164 @@ async fn search_messages(params: SearchParams) -> Result<SearchResult, AppError>
 
     // Add show_only_links filter
     if params.show_only_links {
-        sql.push_str(" AND (instr(m.text, 'http://') > 0 OR instr(m.text, 'https://') > 0)");
+        sql.push_str(&format!(" AND {}", HAS_LINK_SQL));
     }
 
     // Add show_only_attachments filter
     if params.show_only_attachments {
-        sql.push_str(" AND EXISTS (
-            SELECT 1 
-            FROM attachment a 
-            JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID 
-            WHERE maj.message_id = m.ROWID
-        )");
+        sql.push_str(&format!(" AND {}", HAS_ATTACHMENT_SQL));
     }
 
     // Add attachment type filter if a specific type is selected
     if params.attachment_type != "all" {
-        sql.push_str(" AND EXISTS (
-            SELECT 1 
-            FROM attachment a 
-            JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID 
-            WHERE maj.message_id = m.ROWID
-            AND CASE 
-                WHEN a.mime_type LIKE 'image/%' THEN 'image'
-                WHEN a.mime_type LIKE 'video/%' THEN 'video'
-                WHEN a.mime_type = 'application/pdf' THEN 'pdf'
-                WHEN a.mime_type LIKE 'audio/%' THEN 'audio'
-                ELSE 'other'
-            END = ?
-        )");
+        sql.push_str(&format!(" AND {}", has_attachment_category_sql()));
+        query_params.push(Box::new(params.attachment_type.clone()));
+    }
+
+    // Add delivery filters
+    if params.failed_only {
+        sql.push_str(" AND m.is_from_me = 1 AND m.error != 0");
+    }
+    if params.sms_only {
+        sql.push_str(" AND m.service = 'SMS'");
+    }
+    // Read receipts only exist for the user's messages; unanswered means
+    // nothing came back in the same chat afterwards
+    if params.read_unanswered {
+        sql.push_str(
+            r#" AND m.is_from_me = 1 AND m.is_read = 1 AND NOT EXISTS (
+                SELECT 1 FROM message reply
+                JOIN chat_message_join reply_cmj ON reply.ROWID = reply_cmj.message_id
+                WHERE reply_cmj.chat_id = cmj.chat_id AND reply.is_from_me = 0 AND reply.date > m.date
+            )"#,
+        );
+    }
+
+    // Add message kind filter
+    if !params.kinds.is_empty() {
+        let conditions = params
+            .kinds
+            .iter()
+            .map(|kind| message_kind::kind_sql(kind))
+            .collect::<Result<Vec<_>, _>>()?;
+        sql.push_str(&format!(" AND ({})", conditions.join(" OR ")));
+    }
+
+    // Add mentions filter. instr skips bodies without any mention before they are decoded.
+    if let Some(mentions) = &params.mentions {
+        let handles = match mentions {
+            MentionFilter::Me => my_handles(conn)?,
+            MentionFilter::Contact(identifier) => {
+                let identifier = aliases::Aliases::load().expand(identifier);
+                identifier
+                    .contact_id
+                    .iter()
+                    .chain(&identifier.phones)
+                    .chain(&identifier.emails)
+                    .cloned()
+                    .collect()
+            },
+        };
+        sql.push_str(" AND instr(m.attributedBody, CAST('__kIMMentionConfirmedMention' AS BLOB)) > 0 AND mentions_match(m.attributedBody, ?)");
+        query_params.push(Box::new(handles.join("\n")));
+    }
+
+    // Add bookmark filters, read from the attached app database
+    if params.bookmarked_only || !params.bookmark_tags.is_empty() || !params.chat_labels.is_empty() {
+        app_db::attach(conn)?;
+    }
+    if params.bookmarked_only {
+        sql.push_str(" AND m.guid IN (SELECT message_guid FROM app.bookmark)");
+    }
+    if !params.bookmark_tags.is_empty() {
+        let placeholders = vec!["?"; params.bookmark_tags.len()];
+        sql.push_str(&format!(
+            " AND m.guid IN (SELECT message_guid FROM app.bookmark_tag WHERE tag IN ({}))",
+            placeholders.join(", ")
+        ));
+        for tag in &params.bookmark_tags {
+            query_params.push(Box::new(tag.trim().to_string()));
+        }
+    }
+
+    // Add chat label filter
+    if !params.chat_labels.is_empty() {
+        let placeholders = vec!["?"; params.chat_labels.len()];
+        sql.push_str(&format!(
+            " AND cmj.chat_id IN (SELECT ROWID FROM chat WHERE guid IN (SELECT chat_guid FROM app.chat_label WHERE label IN ({})))",
+            placeholders.join(", ")
+        ));
+        for label in &params.chat_labels {
+            query_params.push(Box::new(label.trim().to_string()));
+        }
+    }
+
+    // Add from: operators; any of them may match. Besides handles matching the
+    // value directly, contacts and local names containing it match, and so does
+    // every handle linked to the same person as a matching handle.
+    if !parsed.from.is_empty() {
+        let aliases = aliases::Aliases::load();
+        let mut conditions = Vec::new();
+        for sender in &parsed.from {
+            let digits = normalize_phone_number(sender);
+            if sender.eq_ignore_ascii_case("me") {
+                conditions.push("m.is_from_me = 1".to_string());
+                continue;
+            }
+
+            let (condition, linked) = if digits.len() >= 7 && !sender.chars().any(|c| c.is_alphabetic()) {
+                let last_10 = digits[digits.len().saturating_sub(10)..].to_string();
+                let linked = aliases.linked_keys_where(|key| key.ends_with(&last_10));
+                query_params.push(Box::new(format!("%{}", last_10)));
+                ("REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(h.id, '+', ''), '-', ''), ' ', ''), '(', ''), ')', '') LIKE ?", linked)
+            } else {
+                let folded = text::fold_text(sender);
+                let lowered = sender.to_lowercase();
+                let mut named = contacts::directory().handle_keys_named(&folded);
+                named.extend(aliases.handle_keys_named(&folded));
+                let mut linked = aliases.linked_keys_where(|key| key.contains(&lowered) || named.iter().any(|n| n == key));
+                linked.extend(named);
+                query_params.push(Box::new(sender.clone()));
+                ("instr(lower(h.id), lower(?)) > 0", linked)
+            };
+
+            let rowids: Vec<String> = handle_rowids(conn, &linked)?.iter().map(|id| id.to_string()).collect();
+            let by_key = if rowids.is_empty() {
+                String::new()
+            } else {
+                format!(" OR m.handle_id IN ({})", rowids.join(", "))
+            };
+            conditions.push(format!("(m.is_from_me = 0 AND ({}{}))", condition, by_key));
+        }
+        sql.push_str(&format!(" AND ({})", conditions.join(" OR ")));
+    }
+
+    // Add has: operators; all of them must match
+    for kind in &parsed.has {
+        match kind.as_str() {
+            "link" => sql.push_str(&format!(" AND {}", HAS_LINK_SQL)),
+            "attachment" => sql.push_str(&format!(" AND {}", HAS_ATTACHMENT_SQL)),
+            other => match attachment_data::has_sql(other) {
+                Some(condition) => sql.push_str(&format!(" AND {}", condition)),
+                None => {
+                    sql.push_str(&format!(" AND {}", has_attachment_category_sql()));
+                    query_params.push(Box::new(other.to_string()));
+                },
+            },
+        }
+    }
+
+    // Add in: operators, matching a conversation by display name or identifier
+    if !parsed.in_chat.is_empty() {
+        let conditions = vec!["display_name = ? COLLATE NOCASE OR chat_identifier = ?"; parsed.in_chat.len()];
+        sql.push_str(&format!(
+            " AND cmj.chat_id IN (SELECT ROWID FROM chat WHERE {})",
+            conditions.join(" OR ")
+        ));
+        for name in &parsed.in_chat {
+            query_params.push(Box::new(name.clone()));
+            query_params.push(Box::new(name.clone()));
+        }
     }
 
     // Add conversation type filter
@@ -1081,89 +1816,405 @@ async fn search_messages(params: SearchParams) -> Result<SearchResult, AppError>
         sql.push_str(")");
     }
 
-    // Add ORDER BY clause
-    sql.push_str(" ORDER BY m.date ");
-    sql.push_str(&params.sort_direction.to_uppercase());
-    sql.push_str(" LIMIT 100");
+    Ok(SearchFilters {
+        sql,
+        params: query_params,
+        folded_query,
+        fuzzy_terms,
+        highlighter,
+    })
+}
 
-    println!("Executing SQL: {}", sql);
+// Count hits per conversation, sender, month and attachment category in one pass
+fn search_facets(conn: &Connection, filters: &SearchFilters) -> Result<SearchFacets, AppError> {
+    let sql = format!(r#"
+        SELECT DISTINCT
+            m.ROWID,
+            cmj.chat_id,
+            (SELECT c.display_name FROM chat c WHERE c.ROWID = cmj.chat_id) as display_name,
+            (SELECT c.chat_identifier FROM chat c WHERE c.ROWID = cmj.chat_id) as chat_identifier,
+            (SELECT c.guid FROM chat c WHERE c.ROWID = cmj.chat_id) as chat_guid,
+            m.is_from_me,
+            COALESCE(h.uncanonicalized_id, h.id) as sender_id,
+            m.date,
+            (
+                SELECT group_concat(DISTINCT {})
+                FROM attachment a
+                JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID
+                WHERE maj.message_id = m.ROWID
+            ) as attachment_types
+        {} {}
+    "#, ATTACHMENT_CATEGORY_SQL, SEARCH_FROM, filters.sql);
+
+    let aliases = aliases::Aliases::load();
+    let contacts = contacts::directory();
+    let chat_labels = app_db::open_app_db()
+        .and_then(|app_conn| chat_meta::labels_by_chat(&app_conn))
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to read conversation labels: {}", e);
+            std::collections::HashMap::new()
+        });
+
+    let mut conversations: std::collections::HashMap<String, (Option<String>, i64)> = std::collections::HashMap::new();
+    // Senders are grouped by person; the first handle seen is the key
+    let mut senders: std::collections::HashMap<String, FacetCount> = std::collections::HashMap::new();
+    let mut labels: std::collections::HashMap<String, i64> = std::collections::HashMap::new();
+    let mut months: std::collections::BTreeMap<String, i64> = std::collections::BTreeMap::new();
+    let mut attachment_types: std::collections::HashMap<String, i64> = std::collections::HashMap::new();
+    let mut total_hits = 0;
 
     let mut stmt = conn.prepare(&sql)?;
-    
-    // Create a vector to store the parameters
-    let mut query_params: Vec<&dyn rusqlite::ToSql> = Vec::new();
-    
-    // Add attachment type parameter if needed
-    if params.attachment_type != "all" {
-        sql.push_str(" AND EXISTS (
-            SELECT 1 
-            FROM attachment a 
-            JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID 
-            WHERE maj.message_id = m.ROWID
-            AND CASE 
-                WHEN a.mime_type LIKE 'image/%' THEN 'image'
-                WHEN a.mime_type LIKE 'video/%' THEN 'video'
-                WHEN a.mime_type = 'application/pdf' THEN 'pdf'
-                WHEN a.mime_type LIKE 'audio/%' THEN 'audio'
-                ELSE 'other'
-            END = ?
-        )");
-        query_params.push(&params.attachment_type);
-    }
-    
-    let message_iter = stmt.query_map(rusqlite::params_from_iter(query_params.iter()), |row| {
-        let message_id: i64 = row.get(0)?;
-        let text: Option<String> = row.get(1)?;
-        
-        let date: Result<i64, rusqlite::Error> = row.get(2);
-        let date = match date {
-            Ok(date) => apple_time_to_unix(date / 1_000_000_000),
-            Err(_) => 0,
-        };
-        
-        let is_from_me: Result<i64, rusqlite::Error> = row.get(3);
-        let is_from_me = match is_from_me {
-            Ok(value) => value == 1,
-            Err(_) => false,
+    let mut rows = stmt.query(rusqlite::params_from_iter(filters.params.iter()))?;
+    while let Some(row) = rows.next()? {
+        total_hits += 1;
+
+        let chat_id: i64 = row.get(1)?;
+        let chat_guid: Option<String> = row.get(4)?;
+        match conversations.entry(chat_id.to_string()) {
+            std::collections::hash_map::Entry::Occupied(mut entry) => entry.get_mut().1 += 1,
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let name = conversation_name(row.get(2)?, row.get(3)?, &aliases);
+                entry.insert((name, 1));
+            },
+        }
+        for label in chat_guid.as_ref().and_then(|guid| chat_labels.get(guid)).into_iter().flatten() {
+            *labels.entry(label.clone()).or_insert(0) += 1;
+        }
+
+        let is_from_me: bool = row.get::<_, Option<i64>>(5)?.unwrap_or(0) == 1;
+        let sender: Option<String> = row.get(6)?;
+        let (identity, key, label) = match sender {
+            _ if is_from_me => ("me".to_string(), "me".to_string(), None),
+            Some(handle) => {
+                let label = aliases.display_name(&handle).or_else(|| contacts.name(&handle)).map(str::to_string);
+                (aliases.identity_key(&handle), handle, label)
+            },
+            None => (String::new(), String::new(), None),
         };
+        senders.entry(identity).or_insert(FacetCount { key, label, count: 0 }).count += 1;
 
-        let chat_id: Result<i64, rusqlite::Error> = row.get(4);
-        let chat_id = match chat_id {
-            Ok(id) => Some(id.to_string()),
-            Err(_) => None,
+        let date: i64 = row.get::<_, Option<i64>>(7)?.unwrap_or(0);
+        let month = chrono::DateTime::from_timestamp(apple_time_to_unix(date / 1_000_000_000), 0)
+            .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m").to_string());
+        if let Some(month) = month {
+            *months.entry(month).or_insert(0) += 1;
+        }
+
+        let categories: Option<String> = row.get(8)?;
+        for category in categories.as_deref().unwrap_or("").split(',').filter(|c| !c.is_empty()) {
+            *attachment_types.entry(category.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    let by_count = |a: &FacetCount, b: &FacetCount| b.count.cmp(&a.count).then(a.key.cmp(&b.key));
+
+    let mut conversations: Vec<FacetCount> = conversations
+        .into_iter()
+        .map(|(key, (label, count))| FacetCount { key, label, count })
+        .collect();
+    conversations.sort_by(by_count);
+
+    let mut senders: Vec<FacetCount> = senders.into_values().collect();
+    senders.sort_by(by_count);
+
+    let mut chat_labels: Vec<FacetCount> = labels
+        .into_iter()
+        .map(|(key, count)| FacetCount { key, label: None, count })
+        .collect();
+    chat_labels.sort_by(by_count);
+
+    let mut attachment_types: Vec<FacetCount> = attachment_types
+        .into_iter()
+        .map(|(key, count)| FacetCount { key, label: None, count })
+        .collect();
+    attachment_types.sort_by(by_count);
+
+    Ok(SearchFacets {
+        total_hits,
+        conversations,
+        senders,
+        months: months
+            .into_iter()
+            .map(|(key, count)| FacetCount { key, label: None, count })
+            .collect(),
+        attachment_types,
+        chat_labels,
+    })
+}
+
+// Fill in search-only fields: highlights, snippet and surrounding messages
+fn decorate_search_hit(conn: &Connection, msg: &mut Message, filters: &SearchFilters, params: &SearchParams) {
+    if let Some(highlighter) = &filters.highlighter {
+        msg.highlights = highlighter.highlight(&msg.text);
+        msg.snippet = msg
+            .highlights
+            .first()
+            .map(|&range| text::snippet(&msg.text, range, SNIPPET_CONTEXT_CHARS));
+    }
+
+    if params.context_before > 0 || params.context_after > 0 {
+        let chat_id = match msg.chat_id.as_deref().and_then(|id| id.parse::<i64>().ok()) {
+            Some(id) => id,
+            None => return,
         };
-        
-        let sender_id: Result<String, rusqlite::Error> = row.get(6);
-        let sender_name = match sender_id {
-            Ok(id) if !is_from_me => Some(id),
-            _ => None,
+        match messages_around(conn, chat_id, msg.id, params.context_before, params.context_after) {
+            Ok((before, after)) => {
+                msg.context_before = before;
+                msg.context_after = after;
+            },
+            Err(e) => warn!("Could not load context for message {}: {}", msg.id, e),
+        }
+    }
+}
+
+// Messages sent to the UI per search-results event
+const SEARCH_BATCH_SIZE: usize = 25;
+
+// Payload of the search-results event. The last event of a search has done set.
+#[derive(Serialize, Clone)]
+struct SearchBatch<'a> {
+    search_id: u64,
+    messages: &'a [Message],
+    done: bool,
+}
+
+// Treat SQLite interrupts and cancelled jobs the same way
+fn search_error(error: rusqlite::Error, job: &search_jobs::SearchJob) -> AppError {
+    match &error {
+        rusqlite::Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::OperationInterrupted => AppError::SearchCancelled(job.id),
+        _ if job.is_cancelled() => AppError::SearchCancelled(job.id),
+        _ => AppError::DatabaseQueryError(error),
+    }
+}
+
+// Number of messages after `message_id` (by ROWID) that match `params`
+fn count_search_hits_after(
+    conn: &Connection,
+    params: &SearchParams,
+    vocabulary: &VocabularyState,
+    message_id: i64,
+) -> Result<usize, AppError> {
+    let mut filters = build_search_filters(conn, params, vocabulary)?;
+    filters.sql.push_str(" AND m.ROWID > ?");
+    filters.params.push(Box::new(message_id));
+
+    let sql = format!("SELECT COUNT(DISTINCT m.ROWID) {} {}", SEARCH_FROM, filters.sql);
+    let count: i64 = conn.query_row(&sql, rusqlite::params_from_iter(filters.params.iter()), |row| row.get(0))?;
+    Ok(count as usize)
+}
+
+// Scores every hit and returns the ROWIDs of the best SEARCH_LIMIT, best first:
+// by BM25 when sorting by relevance, otherwise fuzzy hits by edit distance.
+// Only the text of each hit is read, so the whole match set can be scored;
+// ties keep the requested date order.
+fn rank_search_hits(
+    conn: &Connection,
+    filters: &SearchFilters,
+    params: &SearchParams,
+    vocabulary: &VocabularyState,
+    by_relevance: bool,
+    job: &search_jobs::SearchJob,
+) -> Result<Vec<i64>, AppError> {
+    let vocabulary = if by_relevance {
+        let mut vocabulary = vocabulary.0.lock().map_err(|e| AppError::OtherError(e.to_string()))?;
+        vocabulary.update(conn).map_err(|e| search_error(e, job))?;
+        Some(vocabulary)
+    } else {
+        None
+    };
+    let terms: Vec<Vec<(String, usize)>> = if filters.fuzzy_terms.is_empty() {
+        text::words(&filters.folded_query).map(|word| vec![(word.to_string(), 0)]).collect()
+    } else {
+        filters.fuzzy_terms.clone()
+    };
+
+    let sql = format!("SELECT DISTINCT m.ROWID, m.date, m.text {} {}", SEARCH_FROM, filters.sql);
+    let mut stmt = conn.prepare(&sql).map_err(|e| search_error(e, job))?;
+    let mut rows = stmt
+        .query(rusqlite::params_from_iter(filters.params.iter()))
+        .map_err(|e| search_error(e, job))?;
+
+    let mut scored: Vec<(f64, i64, i64)> = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| search_error(e, job))? {
+        if job.is_cancelled() {
+            return Err(AppError::SearchCancelled(job.id));
+        }
+        let message_text: String = row.get::<_, Option<String>>(2)?.unwrap_or_default();
+        let score = match (&vocabulary, &filters.highlighter) {
+            // Fewer edits rank higher
+            (None, _) => -(fuzzy_score(&message_text, &filters.fuzzy_terms) as f64),
+            // Patterns have no vocabulary terms, so rank by number of matches
+            (Some(_), Some(text::Highlighter::Pattern(regex))) => regex.find_iter(&message_text).count() as f64,
+            (Some(vocabulary), _) => vocabulary.bm25(&text::fold_text(&message_text), &terms),
         };
+        scored.push((score, row.get(1)?, row.get(0)?));
+    }
 
-        let attachment_path: Option<String> = row.get(7).ok();
-        let attachment_mime_type: Option<String> = row.get(8).ok();
-        
-        Ok(Message {
-            id: message_id,
-            text: text.unwrap_or_else(|| "[Attachment or empty message]".to_string()),
-            date,
-            is_from_me,
-            chat_id,
-            sender_name,
-            attachment_path,
-            attachment_mime_type,
-            conversation_name: None,
-        })
+    let ascending = params.sort_direction.eq_ignore_ascii_case("asc");
+    scored.sort_by(|a, b| {
+        let by_date = if ascending { a.1.cmp(&b.1) } else { b.1.cmp(&a.1) };
+        b.0.total_cmp(&a.0).then(by_date)
+    });
+    scored.truncate(SEARCH_LIMIT);
+    Ok(scored.into_iter().map(|(_, _, id)| id).collect())
+}
+
+// Search hits for ranked ROWIDs, in the same order
+fn ranked_messages(conn: &Connection, ids: &[i64]) -> rusqlite::Result<Vec<Message>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let id_list: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(
+        "SELECT DISTINCT {} {} AND m.ROWID IN ({})",
+        message_columns(),
+        SEARCH_FROM,
+        id_list.join(", ")
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut messages: Vec<Message> = stmt.query_map([], message_from_row)?.filter_map(Result::ok).collect();
+
+    let rank: std::collections::HashMap<i64, usize> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+    messages.sort_by_key(|msg| rank.get(&msg.id).copied().unwrap_or(usize::MAX));
+    Ok(messages)
+}
+
+// Runs blocking search work, which holds SQLite and the vocabulary lock, on
+// the blocking thread pool so it can't stall the async runtime's workers
+async fn run_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, AppError> + Send + 'static,
+) -> Result<T, AppError> {
+    tauri::async_runtime::spawn_blocking(work)
+        .await
+        .map_err(|e| AppError::OtherError(format!("Search task failed: {}", e)))?
+}
+
+// Runs execute_search with the managed vocabulary and jobs, off the async workers
+async fn run_search(app: tauri::AppHandle, params: SearchParams) -> Result<SearchResult, AppError> {
+    run_blocking(move || execute_search(&app, &params, &app.state::<VocabularyState>(), &app.state::<SearchJobs>())).await
+}
+
+// Runs as a job: emits search-started with the job id, then search-results batches
+// as messages are found. Starting a search cancels older ones, so only the latest
+// search reaches the UI; cancel_search stops one explicitly.
+#[tauri::command]
+async fn search_messages(app: tauri::AppHandle, params: SearchParams) -> Result<SearchResult, AppError> {
+    println!("Received search params: {:?}", params);
+    let result = run_search(app, params.clone()).await?;
+
+    if let Err(e) = search_history::record(&params) {
+        warn!("Failed to record search history: {}", e);
+    }
+    Ok(result)
+}
+
+fn execute_search(
+    app: &tauri::AppHandle,
+    params: &SearchParams,
+    vocabulary: &VocabularyState,
+    jobs: &SearchJobs,
+) -> Result<SearchResult, AppError> {
+    
+    let conn = open_imessage_db()?;
+    let job = jobs.start(&conn);
+    let emit_batch = |messages: &[Message], done: bool| {
+        let batch = SearchBatch { search_id: job.id, messages, done };
+        if let Err(e) = app.emit("search-results", batch) {
+            warn!("Failed to emit search results: {}", e);
+        }
+    };
+    if let Err(e) = app.emit("search-started", job.id) {
+        warn!("Failed to emit search start: {}", e);
+    }
+
+    let filters = build_search_filters(&conn, params, vocabulary).map_err(|e| match e {
+        AppError::DatabaseQueryError(e) => search_error(e, &job),
+        e => e,
     })?;
+    let by_relevance = params.sort == SortOrder::Relevance && filters.highlighter.is_some();
+    // Ranked results can only be sent once every hit has been scored
+    let ranked = by_relevance || !filters.fuzzy_terms.is_empty();
 
     let mut messages = Vec::new();
-    for message in message_iter {
-        if let Ok(msg) = message {
+    let mut sent = 0;
+    if ranked {
+        let ids = rank_search_hits(&conn, &filters, params, vocabulary, by_relevance, &job)?;
+        messages = ranked_messages(&conn, &ids).map_err(|e| search_error(e, &job))?;
+        for msg in &mut messages {
+            decorate_search_hit(&conn, msg, &filters, params);
+        }
+        annotate_messages(&mut messages);
+        for chunk in messages.chunks(SEARCH_BATCH_SIZE) {
+            if job.is_cancelled() {
+                return Err(AppError::SearchCancelled(job.id));
+            }
+            emit_batch(chunk, false);
+        }
+        sent = messages.len();
+    } else {
+        let sql = format!(
+            "SELECT DISTINCT {} {} {} ORDER BY m.date {} LIMIT {}",
+            message_columns(),
+            SEARCH_FROM,
+            filters.sql,
+            params.sort_direction.to_uppercase(),
+            SEARCH_LIMIT
+        );
+        println!("Executing SQL: {}", sql);
+
+        let mut stmt = conn.prepare(&sql)?;
+        let message_iter = stmt
+            .query_map(rusqlite::params_from_iter(filters.params.iter()), message_from_row)
+            .map_err(|e| search_error(e, &job))?;
+
+        for message in message_iter {
+            if job.is_cancelled() {
+                return Err(AppError::SearchCancelled(job.id));
+            }
+            let mut msg = match message {
+                Ok(msg) => msg,
+                Err(e) => match search_error(e, &job) {
+                    AppError::SearchCancelled(id) => return Err(AppError::SearchCancelled(id)),
+                    _ => continue,
+                },
+            };
+            decorate_search_hit(&conn, &mut msg, &filters, params);
             messages.push(msg);
+
+            if messages.len() - sent == SEARCH_BATCH_SIZE {
+                annotate_messages(&mut messages[sent..]);
+                emit_batch(&messages[sent..], false);
+                sent = messages.len();
+            }
         }
     }
 
+    // Offer corrected queries when an exact search finds (almost) nothing
+    let mut suggestions = Vec::new();
+    let exact_mode = matches!(params.match_mode, MatchMode::Substring | MatchMode::Word);
+    if exact_mode && !filters.folded_query.is_empty() && messages.len() < SUGGESTION_THRESHOLD {
+        let mut vocabulary = vocabulary.0.lock().map_err(|e| AppError::OtherError(e.to_string()))?;
+        vocabulary.update(&conn).map_err(|e| search_error(e, &job))?;
+        suggestions = vocabulary.suggest_queries(&filters.folded_query, MAX_SUGGESTIONS);
+    }
+
+    let facets = if params.include_facets {
+        Some(search_facets(&conn, &filters).map_err(|e| match e {
+            AppError::DatabaseQueryError(e) => search_error(e, &job),
+            e => e,
+        })?)
+    } else {
+        None
+    };
+
+    if job.is_cancelled() {
+        return Err(AppError::SearchCancelled(job.id));
+    }
+    annotate_messages(&mut messages[sent..]);
+    emit_batch(&messages[sent..], true);
+
     println!("Found {} messages", messages.len());
-    Ok(SearchResult { messages })
+    Ok(SearchResult { search_id: job.id, messages, suggestions, facets })
 }
 
 // Helper function to convert date string to Apple timestamp
@@ -1208,10 +2259,68 @@ pub fn run() {
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_notification::init())
+        .manage(VocabularyState::default())
+        .manage(SearchJobs::default())
+        .manage(SuggestIndexState::default())
+        .manage(app_db::AppDbState::default())
+        .register_asynchronous_uri_scheme_protocol(attachments::SCHEME, |_ctx, request, responder| {
+            attachments::serve(request, responder)
+        })
+        .register_asynchronous_uri_scheme_protocol(thumbnails::SCHEME, |_ctx, request, responder| {
+            thumbnails::serve(request, responder)
+        })
+        .setup(|app| {
+            suggest::spawn_build(app.handle().clone());
+            reminders::spawn_poller(app.handle().clone());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_conversations,
             get_messages,
+            get_messages_around,
             search_messages,
+            search_jobs::cancel_search,
+            saved_searches::create_saved_search,
+            saved_searches::update_saved_search,
+            saved_searches::delete_saved_search,
+            saved_searches::list_saved_searches,
+            saved_searches::run_saved_search,
+            search_history::list_search_history,
+            search_history::rerun_search_history,
+            search_history::delete_search_history_entry,
+            search_history::clear_search_history,
+            search_history::get_history_enabled,
+            search_history::set_history_enabled,
+            search_history::search_history_completions,
+            suggest::suggest,
+            unanswered::find_unanswered,
+            reminders::set_reminder,
+            reminders::complete_reminder,
+            reminders::delete_reminder,
+            reminders::list_reminders,
+            bookmarks::set_bookmark,
+            bookmarks::delete_bookmark,
+            bookmarks::list_bookmarks,
+            bookmarks::list_bookmark_tags,
+            bookmarks::export_bookmarks,
+            chat_meta::set_chat_meta,
+            chat_meta::list_chat_labels,
+            aliases::list_people,
+            aliases::create_person,
+            aliases::update_person,
+            aliases::delete_person,
+            aliases::set_handle_name,
+            aliases::list_handle_aliases,
+            payments::get_payments,
+            payments::export_payments,
+            attachments::get_message_attachment_info,
+            attachments::get_attachments_root,
+            attachments::set_attachments_root,
+            thumbnails::get_thumbnail_cache_info,
+            thumbnails::set_thumbnail_cache_limit,
+            thumbnails::clear_thumbnail_cache,
+            gallery::get_attachments,
             read_contacts,
             check_permissions,
             open_imessage_conversation,
@@ -1418,8 +2527,8 @@ async fn restart_app(app_handle: tauri::AppHandle) {
     app_handle.restart();
 }
 
-#[derive(Debug, Serialize)]
-struct OpenGraphData {
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct OpenGraphData {
     title: Option<String>,
     description: Option<String>,
     image: Option<String>,
@@ -1428,7 +2537,16 @@ struct OpenGraphData {
 }
 
 #[tauri::command]
-async fn fetch_opengraph_data(url: String) -> Result<OpenGraphData, String> {
+async fn fetch_opengraph_data(url: String, message_guid: Option<String>) -> Result<OpenGraphData, String> {
+    // Prefer the preview Messages archived with the link, which needs no network
+    if let Some(guid) = &message_guid {
+        match open_imessage_db().and_then(|conn| link_preview::archived(&conn, guid)) {
+            Ok(Some(data)) => return Ok(data),
+            Ok(None) => {},
+            Err(e) => warn!("Failed to read archived preview for {}: {}", guid, e),
+        }
+    }
+
     let client = reqwest::Client::new();
     
     // Set a reasonable timeout
@@ -1494,3 +2612,92 @@ async fn fetch_opengraph_data(url: String) -> Result<OpenGraphData, String> {
     Ok(data)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The chat.db tables and columns message_columns() reads
+    fn test_chat_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE message (
+                ROWID INTEGER PRIMARY KEY, guid TEXT, text TEXT, handle_id INTEGER, service TEXT,
+                date INTEGER, date_read INTEGER, date_delivered INTEGER, is_delivered INTEGER,
+                is_from_me INTEGER, is_read INTEGER, is_sent INTEGER, error INTEGER,
+                is_audio_message INTEGER, is_played INTEGER, attributedBody BLOB,
+                balloon_bundle_id TEXT, expressive_send_style_id TEXT, payload_data BLOB
+            );
+            CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT, uncanonicalized_id TEXT);
+            CREATE TABLE chat (ROWID INTEGER PRIMARY KEY, display_name TEXT, guid TEXT);
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+            CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER);
+            CREATE TABLE attachment (ROWID INTEGER PRIMARY KEY, filename TEXT, mime_type TEXT, is_sticker INTEGER);
+            CREATE TABLE message_attachment_join (message_id INTEGER, attachment_id INTEGER);
+
+            INSERT INTO handle VALUES (1, '+15551234567', NULL);
+            INSERT INTO chat VALUES (3, 'Family', 'chat-3');
+            INSERT INTO chat_handle_join VALUES (3, 1);
+            INSERT INTO message VALUES (
+                10, 'guid-10', 'hello', 1, 'iMessage',
+                1000000000000, 1002000000000, 1001000000000, 1,
+                0, 1, 1, 0,
+                0, 0, NULL,
+                NULL, 'com.apple.messages.effect.CKConfettiEffect', NULL
+            );
+            INSERT INTO chat_message_join VALUES (3, 10);
+            INSERT INTO attachment VALUES (1, '~/Library/Messages/Attachments/a/b/photo.jpg', 'image/jpeg', 0);
+            INSERT INTO message_attachment_join VALUES (10, 1);
+            "#,
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn message_row_reads_every_column_group() {
+        let conn = test_chat_db();
+        let sql = format!(
+            r#"
+            SELECT {}, c.display_name as conversation_name
+            FROM message m
+            INNER JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+            INNER JOIN chat c ON cmj.chat_id = c.ROWID
+            LEFT JOIN handle h ON m.handle_id = h.ROWID
+            "#,
+            message_columns()
+        );
+        let message = conn.query_row(&sql, [], message_from_row).unwrap();
+
+        assert_eq!(message.id, 10);
+        assert_eq!(message.guid, "guid-10");
+        assert_eq!(message.chat_id.as_deref(), Some("3"));
+        assert_eq!(message.sender_handle.as_deref(), Some("+15551234567"));
+        assert_eq!(message.service.as_deref(), Some("iMessage"));
+        assert_eq!(message.attachment_mime_type.as_deref(), Some("image/jpeg"));
+        assert!(message.status.is_sent && message.status.is_delivered && message.status.is_read);
+        assert_eq!(message.status.date_delivered, Some(apple_time_to_unix(1001)));
+        assert_eq!(message.status.date_read, Some(apple_time_to_unix(1002)));
+        assert!(matches!(message.kind, message_kind::MessageKind::Effect { is_screen_effect: true, .. }));
+        assert!(message.link_preview.is_none());
+        assert_eq!(message.conversation_name.as_deref(), Some("Family"));
+    }
+
+    #[test]
+    fn conversations_list_without_the_app_database() {
+        let conn = test_chat_db();
+        let mut stmt = conn.prepare(&conversations_query(false)).unwrap();
+        let chats: Vec<(i64, String, bool, bool, Option<String>)> = stmt
+            .query_map(rusqlite::params![false, None::<String>], |row| {
+                Ok((row.get(0)?, row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chats, vec![(3, "chat-3".to_string(), false, false, None)]);
+
+        let labeled = stmt.query_map(rusqlite::params![false, Some("Work")], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(labeled.count(), 0);
+    }
+}
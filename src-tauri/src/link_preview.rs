@@ -0,0 +1,100 @@
+// Link previews archived by Messages.
+//
+// For messages with the URLBalloonProvider balloon, payload_data holds the
+// preview Messages fetched when the link was sent, as an NSKeyedArchiver plist
+// around an LPLinkMetadata object. Decoding it gives previews without a
+// network request, including for sites that no longer exist.
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{keyed_archive::KeyedArchive, AppError, OpenGraphData};
+
+pub const URL_BALLOON_BUNDLE_ID: &str = "com.apple.messages.URLBalloonProvider";
+
+// Payload of link balloons only, so other messages don't load their blobs
+pub const LINK_PAYLOAD_COLUMN: &str =
+    "CASE WHEN m.balloon_bundle_id = 'com.apple.messages.URLBalloonProvider' THEN m.payload_data END";
+
+/// Decodes an archived link preview. Returns None if the payload is not one.
+pub fn decode(payload: &[u8]) -> Option<OpenGraphData> {
+    let archive = KeyedArchive::parse(payload)?;
+    let top = archive.root();
+    // The metadata is usually wrapped in an object with richLinkMetadata
+    let metadata = archive.get(top, "richLinkMetadata").unwrap_or(top);
+
+    let url_of = |key: &str| archive.get(metadata, key).and_then(|meta| archive.field(meta, "URL"));
+    let data = OpenGraphData {
+        title: archive.field(metadata, "title"),
+        description: archive.field(metadata, "summary"),
+        image: url_of("imageMetadata"),
+        favicon: url_of("iconMetadata"),
+        site_name: archive.field(metadata, "siteName"),
+    };
+
+    if data.title.is_none() && data.description.is_none() && data.image.is_none() && data.site_name.is_none() {
+        return None;
+    }
+    Some(data)
+}
+
+/// The archived preview of the link message with this guid, if it has one.
+pub fn archived(conn: &Connection, message_guid: &str) -> Result<Option<OpenGraphData>, AppError> {
+    let payload: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT payload_data FROM message WHERE guid = ? AND balloon_bundle_id = ?",
+            [message_guid, URL_BALLOON_BUNDLE_ID],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(payload.and_then(|payload| decode(&payload)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyed_archive;
+    use plist::{Dictionary, Uid, Value};
+
+    fn object(fields: &[(&str, Value)]) -> Value {
+        let mut dictionary = Dictionary::new();
+        for (key, value) in fields {
+            dictionary.insert(key.to_string(), value.clone());
+        }
+        Value::Dictionary(dictionary)
+    }
+
+    fn uid(index: u64) -> Value {
+        Value::Uid(Uid::new(index))
+    }
+
+    fn string(text: &str) -> Value {
+        Value::String(text.to_string())
+    }
+
+    #[test]
+    fn decodes_wrapped_metadata() {
+        let payload = keyed_archive::encode(vec![
+            string("$null"),
+            object(&[("richLinkMetadata", uid(2))]),
+            object(&[("title", uid(3)), ("summary", uid(0)), ("imageMetadata", uid(4)), ("siteName", uid(6))]),
+            string("A page"),
+            object(&[("URL", uid(5))]),
+            object(&[("NS.relative", uid(7))]),
+            string("Example"),
+            string("https://example.com/image.png"),
+        ]);
+        let data = decode(&payload).unwrap();
+        assert_eq!(data.title.as_deref(), Some("A page"));
+        assert_eq!(data.description, None);
+        assert_eq!(data.image.as_deref(), Some("https://example.com/image.png"));
+        assert_eq!(data.favicon, None);
+        assert_eq!(data.site_name.as_deref(), Some("Example"));
+    }
+
+    #[test]
+    fn rejects_payloads_without_a_preview() {
+        assert!(decode(b"not a plist").is_none());
+        let payload = keyed_archive::encode(vec![string("$null"), object(&[("URL", uid(2))]), string("https://example.com")]);
+        assert!(decode(&payload).is_none());
+    }
+}
@@ -0,0 +1,242 @@
+// Merged view of conversations with the same people.
+//
+// chat.db keeps a separate chat for each service (iMessage, SMS, RCS) and for
+// each handle of a contact. Handles are grouped into identities: handles on
+// the same AddressBook card belong together, and local people join further
+// handles on top. Chats whose participants resolve to the same
+// identities are merged into one conversation whose id lists every chat id,
+// comma separated. get_messages and the search conversation filter accept
+// such ids and interleave the chats.
+use rusqlite::Connection;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{aliases::Aliases, contacts, handle_key, AppError, Conversation};
+
+// Union-find over handle keys and the contact cards and people joining them
+#[derive(Default)]
+struct Identities {
+    parent: HashMap<String, String>,
+}
+
+impl Identities {
+    fn find(&mut self, key: &str) -> String {
+        let mut root = key.to_string();
+        while let Some(parent) = self.parent.get(&root).filter(|p| **p != root) {
+            root = parent.clone();
+        }
+        // Point the path straight at the root so later lookups are short
+        let mut node = key.to_string();
+        while node != root {
+            let next = self.parent.insert(node, root.clone()).unwrap_or_else(|| root.clone());
+            node = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: &str, b: &str) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent.insert(a, b);
+        }
+    }
+}
+
+/// Parses a conversation id, which may list several chat ids separated by commas.
+pub fn parse_chat_ids(conversation_id: &str) -> Result<Vec<i64>, AppError> {
+    let ids = conversation_id
+        .split(',')
+        .map(|id| id.trim().parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AppError::OtherError("Invalid conversation ID".to_string()))?;
+    if ids.is_empty() {
+        return Err(AppError::OtherError("Invalid conversation ID".to_string()));
+    }
+    Ok(ids)
+}
+
+// Participant identities of every chat with at least one handle. `contact_of`
+// gives the AddressBook card of a handle.
+fn participants_by_chat(
+    conn: &Connection,
+    contact_of: impl Fn(&str) -> Option<i64>,
+    aliases: &Aliases,
+) -> Result<HashMap<i64, BTreeSet<String>>, AppError> {
+    let mut stmt = conn.prepare(
+        "SELECT chj.chat_id, h.id FROM chat_handle_join chj JOIN handle h ON chj.handle_id = h.ROWID",
+    )?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut identities = Identities::default();
+    for (_, handle) in &rows {
+        let key = handle_key(handle);
+        if let Some(contact_id) = contact_of(handle) {
+            identities.union(&key, &format!("contact:{}", contact_id));
+        }
+        let person = aliases.identity_key(handle);
+        if person != key {
+            identities.union(&key, &person);
+        }
+    }
+
+    let mut participants: HashMap<i64, BTreeSet<String>> = HashMap::new();
+    for (chat_id, handle) in rows {
+        participants.entry(chat_id).or_default().insert(identities.find(&handle_key(&handle)));
+    }
+    Ok(participants)
+}
+
+/// Merges conversations with the same participants. The merged conversation
+/// takes its name, guid and last message from its most recent chat, and keeps
+/// pinned chats first, most recent first.
+pub fn merge_conversations(conn: &Connection, conversations: Vec<Conversation>) -> Result<Vec<Conversation>, AppError> {
+    let aliases = Aliases::load();
+    let contacts = contacts::directory();
+    let participants = participants_by_chat(conn, |handle| contacts.card(handle).map(|card| card.id), &aliases)?;
+    Ok(merge_by_participants(conversations, &participants))
+}
+
+fn merge_by_participants(
+    conversations: Vec<Conversation>,
+    participants: &HashMap<i64, BTreeSet<String>>,
+) -> Vec<Conversation> {
+    let mut merged: Vec<Conversation> = Vec::new();
+    let mut index_by_participants: HashMap<&BTreeSet<String>, usize> = HashMap::new();
+    for conversation in conversations {
+        let group = conversation
+            .id
+            .parse::<i64>()
+            .ok()
+            .and_then(|id| participants.get(&id))
+            .filter(|p| !p.is_empty());
+        let Some(group) = group else {
+            merged.push(conversation);
+            continue;
+        };
+
+        match index_by_participants.get(group) {
+            Some(&index) => {
+                let target = &mut merged[index];
+                target.id = format!("{},{}", target.id, conversation.id);
+                if conversation.last_message_date > target.last_message_date {
+                    target.guid = conversation.guid;
+                    target.name = conversation.name.or(target.name.take());
+                    target.last_message = conversation.last_message;
+                    target.last_message_date = conversation.last_message_date;
+                }
+                target.meta.pinned |= conversation.meta.pinned;
+                target.meta.hidden &= conversation.meta.hidden;
+                if target.meta.color.is_none() {
+                    target.meta.color = conversation.meta.color;
+                }
+                for label in conversation.meta.labels {
+                    if !target.meta.labels.iter().any(|l| l.eq_ignore_ascii_case(&label)) {
+                        target.meta.labels.push(label);
+                    }
+                }
+            },
+            None => {
+                index_by_participants.insert(group, merged.len());
+                merged.push(conversation);
+            },
+        }
+    }
+
+    // A merged chat may have brought in a pin or a newer message
+    merged.sort_by(|a, b| {
+        b.meta.pinned.cmp(&a.meta.pinned).then(b.last_message_date.cmp(&a.last_message_date))
+    });
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_db;
+
+    // Chats 1-3 are with Ann: her phone and email are on one contact card, and
+    // her work email is linked to her as a local person. Chat 4 is with Bob.
+    fn test_chat_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT);
+            CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER);
+            INSERT INTO handle VALUES (1, '+15551234567'), (2, 'ann@example.com'), (3, 'ann@work.com'), (4, '+15559876543');
+            INSERT INTO chat_handle_join VALUES (1, 1), (2, 2), (3, 3), (4, 4);
+            "#,
+        )
+        .unwrap();
+        conn
+    }
+
+    fn test_aliases() -> Aliases {
+        let conn = app_db::open_test_db();
+        conn.execute("INSERT INTO person (id, name, created_at, updated_at) VALUES (1, 'Ann', 0, 0)", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO handle_alias (handle_key, handle, person_id) VALUES ('ann@work.com', 'ann@work.com', 1), ('ann@example.com', 'ann@example.com', 1)",
+            [],
+        )
+        .unwrap();
+        Aliases::load_from(&conn).unwrap()
+    }
+
+    fn contact_of(handle: &str) -> Option<i64> {
+        match handle_key(handle).as_str() {
+            "5551234567" | "ann@example.com" => Some(7),
+            _ => None,
+        }
+    }
+
+    fn conversation(id: i64, last_message_date: i64) -> Conversation {
+        Conversation {
+            id: id.to_string(),
+            guid: format!("guid-{}", id),
+            name: Some(format!("chat {}", id)),
+            last_message_date,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_merged_ids() {
+        assert_eq!(parse_chat_ids("3").unwrap(), vec![3]);
+        assert_eq!(parse_chat_ids("3, 5,8").unwrap(), vec![3, 5, 8]);
+        assert!(parse_chat_ids("").is_err());
+        assert!(parse_chat_ids("3,x").is_err());
+    }
+
+    #[test]
+    fn contacts_and_people_join_handles() {
+        let participants = participants_by_chat(&test_chat_db(), contact_of, &test_aliases()).unwrap();
+        assert_eq!(participants[&1], participants[&2]);
+        assert_eq!(participants[&1], participants[&3]);
+        assert_ne!(participants[&1], participants[&4]);
+    }
+
+    #[test]
+    fn contacts_alone_join_handles() {
+        let participants = participants_by_chat(&test_chat_db(), contact_of, &Aliases::default()).unwrap();
+        assert_eq!(participants[&1], participants[&2]);
+        assert_ne!(participants[&1], participants[&3]);
+    }
+
+    #[test]
+    fn merged_conversation_takes_the_latest_chat() {
+        let participants = participants_by_chat(&test_chat_db(), contact_of, &test_aliases()).unwrap();
+        let mut pinned = conversation(4, 10);
+        pinned.meta.pinned = true;
+        let mut labeled = conversation(1, 20);
+        labeled.meta.labels = vec!["Family".to_string()];
+        let conversations = vec![conversation(2, 30), labeled, pinned, conversation(3, 5), conversation(9, 40)];
+
+        let merged = merge_by_participants(conversations, &participants);
+        let ids: Vec<&str> = merged.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["4", "9", "2,1,3"]);
+        assert_eq!(merged[2].guid, "guid-2");
+        assert_eq!(merged[2].last_message_date, 30);
+        assert_eq!(merged[2].meta.labels, vec!["Family"]);
+    }
+}
@@ -0,0 +1,218 @@
+// What kind of message a row is: plain text, a voice note, a sticker, an
+// iMessage app message or a message sent with an effect.
+//
+// A message gets the first kind that applies, in that order, so a voice note
+// sent with an effect is still audio. kind_sql mirrors the same precedence so
+// the kind search filter agrees with the kind returned on messages.
+use serde::{Deserialize, Serialize};
+
+use crate::link_preview::URL_BALLOON_BUNDLE_ID;
+use crate::AppError;
+
+// Screen effects; other styles are bubble effects
+const SCREEN_EFFECT_PREFIX: &str = "com.apple.messages.effect.";
+
+// Conditions on message m for each kind, without precedence
+const AUDIO_SQL: &str = "COALESCE(m.is_audio_message, 0) = 1";
+const STICKER_SQL: &str = r#"EXISTS (
+            SELECT 1
+            FROM attachment a
+            JOIN message_attachment_join maj ON maj.attachment_id = a.ROWID
+            WHERE maj.message_id = m.ROWID AND a.is_sticker = 1
+        )"#;
+const APP_SQL: &str = "COALESCE(m.balloon_bundle_id, '') NOT IN ('', 'com.apple.messages.URLBalloonProvider')";
+const EFFECT_SQL: &str = "COALESCE(m.expressive_send_style_id, '') != ''";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MessageKind {
+    #[default]
+    Text,
+    Audio {
+        is_played: bool,
+    },
+    Sticker,
+    App {
+        bundle_id: String,
+        app_name: String,
+    },
+    Effect {
+        style_id: String,
+        effect: String,          // "slam", "invisible_ink", "confetti", ...
+        is_screen_effect: bool,
+    },
+}
+
+// Short name of an expressive send style
+fn effect_name(style_id: &str) -> String {
+    let name = match style_id.rsplit('.').next().unwrap_or(style_id) {
+        "impact" => "slam",
+        "loud" => "loud",
+        "gentle" => "gentle",
+        "invisibleink" => "invisible_ink",
+        "CKHappyBirthdayEffect" => "balloons",
+        "CKConfettiEffect" => "confetti",
+        "CKLasersEffect" => "lasers",
+        "CKFireworksEffect" => "fireworks",
+        "CKShootingStarEffect" => "shooting_star",
+        "CKSparklesEffect" => "celebration",
+        "CKHeartEffect" => "love",
+        "CKEchoEffect" => "echo",
+        "CKSpotlightEffect" => "spotlight",
+        other => other,
+    };
+    name.to_string()
+}
+
+// Readable name of the app behind a balloon bundle id. Extension balloons look
+// like "com.apple.messages.MSMessageExtensionBalloonPlugin:<team>:<bundle>".
+fn app_name(bundle_id: &str) -> String {
+    let bundle = bundle_id.rsplit(':').next().unwrap_or(bundle_id);
+    let name = match bundle {
+        "com.apple.PassbookUIService.PeerPaymentMessagesExtension" => "Apple Cash",
+        "com.apple.DigitalTouchBalloonProvider" => "Digital Touch",
+        "com.apple.Handwriting.HandwritingProvider" => "Handwriting",
+        "com.apple.SafetyMonitorApp.SafetyMonitorMessages" => "Check In",
+        "com.apple.findmy.FindMyMessagesApp" => "Find My",
+        other => other,
+    };
+    name.to_string()
+}
+
+// Columns read by MessageKind::from_row, in order
+pub fn message_kind_columns() -> String {
+    format!(
+        "m.is_audio_message, {} as is_sticker, m.balloon_bundle_id, m.expressive_send_style_id, m.is_played",
+        STICKER_SQL
+    )
+}
+
+impl MessageKind {
+    pub const COLUMN_COUNT: usize = 5;
+
+    pub fn from_row(row: &rusqlite::Row, first: usize) -> MessageKind {
+        let flag = |i: usize| row.get::<_, Option<i64>>(first + i).ok().flatten().unwrap_or(0) == 1;
+        let text = |i: usize| row.get::<_, Option<String>>(first + i).ok().flatten().filter(|s| !s.is_empty());
+
+        if flag(0) {
+            return MessageKind::Audio { is_played: flag(4) };
+        }
+        if flag(1) {
+            return MessageKind::Sticker;
+        }
+        // Link previews are stored as balloons too, but they are plain messages
+        if let Some(bundle_id) = text(2).filter(|id| id != URL_BALLOON_BUNDLE_ID) {
+            let app_name = app_name(&bundle_id);
+            return MessageKind::App { bundle_id, app_name };
+        }
+        if let Some(style_id) = text(3) {
+            return MessageKind::Effect {
+                effect: effect_name(&style_id),
+                is_screen_effect: style_id.starts_with(SCREEN_EFFECT_PREFIX),
+                style_id,
+            };
+        }
+        MessageKind::Text
+    }
+}
+
+/// Condition on message m matching one kind, with the same precedence as from_row.
+pub fn kind_sql(kind: &str) -> Result<String, AppError> {
+    let sql = match kind {
+        "audio" => AUDIO_SQL.to_string(),
+        "sticker" => format!("(NOT {} AND {})", AUDIO_SQL, STICKER_SQL),
+        "app" => format!("(NOT {} AND NOT {} AND {})", AUDIO_SQL, STICKER_SQL, APP_SQL),
+        "effect" => format!("(NOT {} AND NOT {} AND NOT {} AND {})", AUDIO_SQL, STICKER_SQL, APP_SQL, EFFECT_SQL),
+        "text" => format!("NOT ({} OR {} OR {} OR {})", AUDIO_SQL, STICKER_SQL, APP_SQL, EFFECT_SQL),
+        other => return Err(AppError::OtherError(format!("Unknown message kind: {}", other))),
+    };
+    Ok(sql)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    const KINDS: [&str; 5] = ["audio", "sticker", "app", "effect", "text"];
+
+    // Messages 1-6: voice note sent with an effect, sticker, app balloon,
+    // effect, link preview and plain text
+    fn test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE message (
+                ROWID INTEGER PRIMARY KEY, is_audio_message INTEGER, is_played INTEGER,
+                balloon_bundle_id TEXT, expressive_send_style_id TEXT
+            );
+            CREATE TABLE attachment (ROWID INTEGER PRIMARY KEY, is_sticker INTEGER);
+            CREATE TABLE message_attachment_join (message_id INTEGER, attachment_id INTEGER);
+            INSERT INTO message VALUES
+                (1, 1, 1, NULL, 'com.apple.MobileSMS.expressivesend.impact'),
+                (2, 0, 0, NULL, NULL),
+                (3, 0, 0, 'com.apple.messages.MSMessageExtensionBalloonPlugin:0000:com.apple.findmy.FindMyMessagesApp', NULL),
+                (4, 0, 0, NULL, 'com.apple.messages.effect.CKConfettiEffect'),
+                (5, 0, 0, 'com.apple.messages.URLBalloonProvider', NULL),
+                (6, 0, 0, NULL, '');
+            INSERT INTO attachment VALUES (1, 1);
+            INSERT INTO message_attachment_join VALUES (2, 1);
+            "#,
+        )
+        .unwrap();
+        conn
+    }
+
+    fn kind_of(conn: &Connection, id: i64) -> MessageKind {
+        let sql = format!("SELECT {} FROM message m WHERE m.ROWID = ?", message_kind_columns());
+        conn.query_row(&sql, [id], |row| Ok(MessageKind::from_row(row, 0))).unwrap()
+    }
+
+    #[test]
+    fn reads_kinds_with_precedence() {
+        let conn = test_db();
+        assert_eq!(kind_of(&conn, 1), MessageKind::Audio { is_played: true });
+        assert_eq!(kind_of(&conn, 2), MessageKind::Sticker);
+        assert_eq!(
+            kind_of(&conn, 3),
+            MessageKind::App {
+                bundle_id: "com.apple.messages.MSMessageExtensionBalloonPlugin:0000:com.apple.findmy.FindMyMessagesApp"
+                    .to_string(),
+                app_name: "Find My".to_string(),
+            }
+        );
+        assert_eq!(
+            kind_of(&conn, 4),
+            MessageKind::Effect {
+                style_id: "com.apple.messages.effect.CKConfettiEffect".to_string(),
+                effect: "confetti".to_string(),
+                is_screen_effect: true,
+            }
+        );
+        assert_eq!(kind_of(&conn, 5), MessageKind::Text);
+        assert_eq!(kind_of(&conn, 6), MessageKind::Text);
+    }
+
+    #[test]
+    fn kind_filter_agrees_with_kind_read() {
+        let conn = test_db();
+        for kind in KINDS {
+            let sql = format!("SELECT m.ROWID FROM message m WHERE {} ORDER BY m.ROWID", kind_sql(kind).unwrap());
+            let mut stmt = conn.prepare(&sql).unwrap();
+            let ids: Vec<i64> = stmt.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+            for id in 1..=6 {
+                let read = serde_json::to_value(kind_of(&conn, id)).unwrap();
+                assert_eq!(ids.contains(&id), read["kind"] == kind, "message {} for {}", id, kind);
+            }
+        }
+        assert!(kind_sql("video").is_err());
+    }
+
+    #[test]
+    fn names_effects_and_apps() {
+        assert_eq!(effect_name("com.apple.MobileSMS.expressivesend.invisibleink"), "invisible_ink");
+        assert_eq!(effect_name("com.apple.messages.effect.CKNewEffect"), "CKNewEffect");
+        assert_eq!(app_name("com.apple.DigitalTouchBalloonProvider"), "Digital Touch");
+        assert_eq!(app_name("com.example.Game"), "com.example.Game");
+    }
+}
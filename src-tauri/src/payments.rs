@@ -0,0 +1,482 @@
+// Apple Cash payments and requests sent in Messages.
+//
+// Payments are app balloons from the PeerPaymentMessagesExtension. Their
+// payload_data archive carries the amount, currency and kind of transfer in
+// the query of the message URL, and the caption shown in the bubble ("$25",
+// "Request for $10") under the ldtext key. The decoder reads only those: the
+// URL first, then the caption, never the text the user typed alongside.
+// Amounts are kept in hundredths of the currency unit so totals add exactly.
+use regex::Regex;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::{aliases::Aliases, export, keyed_archive::KeyedArchive, AppError};
+
+const PAYMENT_BALLOON_SQL: &str = "m.balloon_bundle_id LIKE '%com.apple.PassbookUIService.PeerPaymentMessagesExtension'";
+
+// URL query parameters naming the amount, the currency and the kind of transfer
+const AMOUNT_PARAMS: &[&str] = &["amount"];
+const CURRENCY_PARAMS: &[&str] = &["currency", "currencyCode"];
+const KIND_PARAMS: &[&str] = &["type", "action", "transactionType"];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentDirection {
+    Sent,
+    Received,
+    // The user asked the contact for money
+    Requested,
+    // The contact asked the user for money
+    RequestReceived,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Payment {
+    message_guid: String,
+    date: i64,
+    chat_id: String,
+    contact: Option<String>,        // handle of the other person
+    contact_name: Option<String>,
+    direction: PaymentDirection,
+    #[serde(serialize_with = "serialize_cents")]
+    amount: i64,                    // hundredths, sent as a decimal string like "25.00"
+    currency: Option<String>,       // ISO code when known
+    summary: Option<String>,        // text shown in the bubble
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct PaymentTotal {
+    contact: Option<String>,
+    contact_name: Option<String>,
+    currency: Option<String>,
+    #[serde(serialize_with = "serialize_cents")]
+    sent: i64,
+    #[serde(serialize_with = "serialize_cents")]
+    received: i64,
+    #[serde(serialize_with = "serialize_cents")]
+    requested: i64,
+    #[serde(serialize_with = "serialize_cents")]
+    request_received: i64,
+    // received - sent
+    #[serde(serialize_with = "serialize_cents")]
+    net: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PaymentLedger {
+    // Oldest first
+    payments: Vec<Payment>,
+    totals: Vec<PaymentTotal>,
+}
+
+// Amount and currency decoded from a payment payload
+#[derive(Debug, PartialEq)]
+struct DecodedPayment {
+    amount: i64,
+    currency: Option<String>,
+    is_request: bool,
+    summary: Option<String>,
+}
+
+// Hundredths as a decimal string, like "-1250.05"
+fn format_cents(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{}{}.{:02}", sign, cents.unsigned_abs() / 100, cents.unsigned_abs() % 100)
+}
+
+fn serialize_cents<S: Serializer>(cents: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_cents(*cents))
+}
+
+// "1,250.5" as 125050 hundredths
+fn parse_cents(amount: &str) -> Option<i64> {
+    let amount = amount.trim().replace(',', "");
+    let (whole, fraction) = amount.split_once('.').unwrap_or((&amount, ""));
+    if whole.is_empty() || fraction.len() > 2 || !(whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    let fraction: i64 = format!("{:0<2}", fraction).parse().ok()?;
+    whole.parse::<i64>().ok()?.checked_mul(100)?.checked_add(fraction)
+}
+
+fn caption_amount_regex() -> &'static Regex {
+    static AMOUNT: OnceLock<Regex> = OnceLock::new();
+    AMOUNT.get_or_init(|| Regex::new(r"([$€£¥₹])\s?([0-9][0-9,]*(?:\.[0-9]{1,2})?)").unwrap())
+}
+
+fn currency_for_symbol(symbol: &str) -> Option<String> {
+    let code = match symbol {
+        "$" => "USD",
+        "€" => "EUR",
+        "£" => "GBP",
+        "¥" => "JPY",
+        "₹" => "INR",
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+// Amount in the bubble caption, like "$1,250.00" or "Request for $10"
+fn amount_in_caption(caption: &str) -> Option<(i64, Option<String>)> {
+    let captures = caption_amount_regex().captures(caption)?;
+    Some((parse_cents(&captures[2])?, currency_for_symbol(&captures[1])))
+}
+
+// Whether the caption describes a request rather than a payment
+fn caption_is_request(caption: &str) -> bool {
+    caption.trim_start().to_lowercase().starts_with("request")
+}
+
+// Transfer described by the query of a payment URL
+struct PaymentUrl {
+    amount: Option<i64>,
+    currency: Option<String>,
+    is_request: Option<bool>,
+}
+
+fn parse_payment_url(text: &str) -> Option<PaymentUrl> {
+    let url = url::Url::parse(text).ok()?;
+    let param = |names: &[&str]| {
+        url.query_pairs()
+            .find(|(key, _)| names.iter().any(|name| key.eq_ignore_ascii_case(name)))
+            .map(|(_, value)| value.into_owned())
+    };
+    let parsed = PaymentUrl {
+        amount: param(AMOUNT_PARAMS).and_then(|amount| parse_cents(&amount)),
+        currency: param(CURRENCY_PARAMS).map(|c| c.to_uppercase()),
+        is_request: param(KIND_PARAMS).map(|kind| kind.eq_ignore_ascii_case("request")),
+    };
+    parsed.amount.is_some().then_some(parsed)
+}
+
+fn decode(payload: &[u8]) -> Option<DecodedPayment> {
+    let archive = KeyedArchive::parse(payload)?;
+    let root = archive.root();
+    let summary = archive.field(root, "ldtext");
+    let url = archive.field(root, "URL").and_then(|url| parse_payment_url(&url));
+    let caption = summary.as_deref().and_then(amount_in_caption);
+
+    let (amount, currency) = match (&url, caption) {
+        (Some(url), caption) => (url.amount?, url.currency.clone().or(caption.and_then(|(_, c)| c))),
+        (None, Some(caption)) => caption,
+        (None, None) => return None,
+    };
+    let is_request = url
+        .and_then(|url| url.is_request)
+        .unwrap_or_else(|| summary.as_deref().is_some_and(caption_is_request));
+
+    Some(DecodedPayment { amount, currency, is_request, summary })
+}
+
+fn load_payments(since: Option<&str>, until: Option<&str>) -> Result<Vec<Payment>, AppError> {
+    let conn = crate::open_imessage_db()?;
+    query_payments(&conn, since, until, &Aliases::load())
+}
+
+// The contact is the sender's canonical handle, or for the user's own
+// payments the other person in a one-to-one chat. In group chats the user's
+// payments have no known contact.
+fn query_payments(
+    conn: &rusqlite::Connection,
+    since: Option<&str>,
+    until: Option<&str>,
+    aliases: &Aliases,
+) -> Result<Vec<Payment>, AppError> {
+    let mut sql = format!(
+        r#"
+        SELECT
+            m.guid,
+            m.date,
+            m.is_from_me,
+            m.payload_data,
+            cmj.chat_id,
+            (SELECT MIN(ph.id) FROM chat_handle_join pj JOIN handle ph ON ph.ROWID = pj.handle_id
+             WHERE pj.chat_id = cmj.chat_id HAVING COUNT(*) = 1) as partner_id,
+            h.id as sender_id
+        FROM
+            message m
+        INNER JOIN
+            chat_message_join cmj ON m.ROWID = cmj.message_id
+        LEFT JOIN
+            handle h ON m.handle_id = h.ROWID
+        WHERE
+            {} AND m.payload_data IS NOT NULL
+        "#,
+        PAYMENT_BALLOON_SQL
+    );
+    for (date, op) in [(since, ">"), (until, "<")] {
+        if let Some(date) = date {
+            let timestamp = crate::date_to_apple_timestamp(date)
+                .ok_or_else(|| AppError::OtherError(format!("Invalid date: {}", date)))?;
+            sql.push_str(&format!(" AND m.date {} {}", op, timestamp));
+        }
+    }
+    sql.push_str(" ORDER BY m.date, m.ROWID");
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query([])?;
+    let mut payments = Vec::new();
+    while let Some(row) = rows.next()? {
+        let payload: Vec<u8> = row.get(3)?;
+        let Some(decoded) = decode(&payload) else {
+            log::warn!("Could not decode payment {}", row.get::<_, String>(0)?);
+            continue;
+        };
+
+        let is_from_me = row.get::<_, i64>(2)? == 1;
+        let partner: Option<String> = row.get(5)?;
+        let sender: Option<String> = row.get(6)?;
+        let contact = if is_from_me { partner } else { sender.or(partner) };
+        let contact_name = contact.as_deref().and_then(|c| aliases.display_name(c)).map(str::to_string);
+
+        let direction = match (decoded.is_request, is_from_me) {
+            (false, true) => PaymentDirection::Sent,
+            (false, false) => PaymentDirection::Received,
+            (true, true) => PaymentDirection::Requested,
+            (true, false) => PaymentDirection::RequestReceived,
+        };
+        let date: i64 = row.get(1)?;
+
+        payments.push(Payment {
+            message_guid: row.get(0)?,
+            date: crate::apple_time_to_unix(date / 1_000_000_000),
+            chat_id: row.get::<_, i64>(4)?.to_string(),
+            contact,
+            contact_name,
+            direction,
+            amount: decoded.amount,
+            currency: decoded.currency,
+            summary: decoded.summary,
+        });
+    }
+    Ok(payments)
+}
+
+// Totals per contact and currency, largest activity first. Contacts are
+// compared by handle key, so spellings of one number share a row.
+fn totals(payments: &[Payment]) -> Vec<PaymentTotal> {
+    let mut totals: BTreeMap<(Option<String>, Option<String>), PaymentTotal> = BTreeMap::new();
+    for payment in payments {
+        let total = totals
+            .entry((payment.contact.as_deref().map(crate::handle_key), payment.currency.clone()))
+            .or_insert_with(|| PaymentTotal {
+                contact: payment.contact.clone(),
+                currency: payment.currency.clone(),
+                ..Default::default()
+            });
+        if payment.contact_name.is_some() {
+            total.contact_name = payment.contact_name.clone();
+        }
+        match payment.direction {
+            PaymentDirection::Sent => total.sent += payment.amount,
+            PaymentDirection::Received => total.received += payment.amount,
+            PaymentDirection::Requested => total.requested += payment.amount,
+            PaymentDirection::RequestReceived => total.request_received += payment.amount,
+        }
+        total.net = total.received - total.sent;
+    }
+
+    let mut totals: Vec<PaymentTotal> = totals.into_values().collect();
+    totals.sort_by_key(|total| std::cmp::Reverse(total.sent + total.received));
+    totals
+}
+
+/// Apple Cash payments and requests between `since` and `until` (yyyy-MM-dd),
+/// with totals per contact.
+#[tauri::command]
+pub async fn get_payments(since: Option<String>, until: Option<String>) -> Result<PaymentLedger, AppError> {
+    let payments = load_payments(since.as_deref(), until.as_deref())?;
+    let totals = totals(&payments);
+    log::info!("Found {} payments", payments.len());
+    Ok(PaymentLedger { payments, totals })
+}
+
+/// Writes the payment ledger as CSV to `path` (default: Downloads) and returns the path.
+#[tauri::command]
+pub async fn export_payments(path: Option<String>, since: Option<String>, until: Option<String>) -> Result<String, AppError> {
+    let payments = load_payments(since.as_deref(), until.as_deref())?;
+
+    let mut csv = export::csv_row(&["date", "contact", "name", "direction", "amount", "currency", "summary", "message_guid"]);
+    for payment in &payments {
+        let date = chrono::DateTime::from_timestamp(payment.date, 0)
+            .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        let direction = match payment.direction {
+            PaymentDirection::Sent => "sent",
+            PaymentDirection::Received => "received",
+            PaymentDirection::Requested => "requested",
+            PaymentDirection::RequestReceived => "request_received",
+        };
+        csv.push_str(&export::csv_row(&[
+            &date,
+            payment.contact.as_deref().unwrap_or(""),
+            payment.contact_name.as_deref().unwrap_or(""),
+            direction,
+            &format_cents(payment.amount),
+            payment.currency.as_deref().unwrap_or(""),
+            payment.summary.as_deref().unwrap_or(""),
+            &payment.message_guid,
+        ]));
+    }
+
+    export::write_export(export::export_path(path, "imessage-payments", "csv")?, &csv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyed_archive;
+    use plist::{Dictionary, Uid, Value};
+
+    // A payment balloon archive with the given URL and caption
+    fn payload(url: Option<&str>, caption: Option<&str>) -> Vec<u8> {
+        let mut objects = vec![Value::String("$null".to_string()), Value::Dictionary(Dictionary::new())];
+        let mut root = Dictionary::new();
+        for (key, value) in [("URL", url), ("ldtext", caption)] {
+            if let Some(value) = value {
+                root.insert(key.to_string(), Value::Uid(Uid::new(objects.len() as u64)));
+                objects.push(Value::String(value.to_string()));
+            }
+        }
+        objects[1] = Value::Dictionary(root);
+        keyed_archive::encode(objects)
+    }
+
+    fn payment(contact: &str, direction: PaymentDirection, amount: i64) -> Payment {
+        Payment {
+            message_guid: String::new(),
+            date: 0,
+            chat_id: "1".to_string(),
+            contact: Some(contact.to_string()),
+            contact_name: None,
+            direction,
+            amount,
+            currency: Some("USD".to_string()),
+            summary: None,
+        }
+    }
+
+    #[test]
+    fn parses_and_formats_cents() {
+        assert_eq!(parse_cents("25"), Some(2500));
+        assert_eq!(parse_cents("1,250.5"), Some(125050));
+        assert_eq!(parse_cents("0.07"), Some(7));
+        assert_eq!(parse_cents("1.005"), None);
+        assert_eq!(parse_cents("-3"), None);
+        assert_eq!(parse_cents(".5"), None);
+        assert_eq!(format_cents(125050), "1250.50");
+        assert_eq!(format_cents(7), "0.07");
+        assert_eq!(format_cents(-1505), "-15.05");
+    }
+
+    #[test]
+    fn decodes_the_url_first() {
+        let decoded = decode(&payload(
+            Some("https://apple.com/apple-cash?amount=12.30&currency=usd&type=send"),
+            Some("$99 Payment"),
+        ))
+        .unwrap();
+        assert_eq!(
+            decoded,
+            DecodedPayment {
+                amount: 1230,
+                currency: Some("USD".to_string()),
+                is_request: false,
+                summary: Some("$99 Payment".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn url_kind_decides_requests() {
+        let decoded = decode(&payload(Some("https://apple.com/apple-cash?amount=5&type=request"), Some("£5"))).unwrap();
+        assert!(decoded.is_request);
+        assert_eq!(decoded.currency.as_deref(), Some("GBP"));
+    }
+
+    #[test]
+    fn falls_back_to_the_caption() {
+        let decoded = decode(&payload(None, Some("Request for €1,250.00"))).unwrap();
+        assert_eq!(decoded.amount, 125000);
+        assert_eq!(decoded.currency.as_deref(), Some("EUR"));
+        assert!(decoded.is_request);
+
+        // "request" elsewhere in the caption or the URL path doesn't make a request
+        let decoded = decode(&payload(Some("https://apple.com/request/pay?amount=3"), Some("$3 per your request"))).unwrap();
+        assert!(!decoded.is_request);
+        assert_eq!(decoded.amount, 300);
+    }
+
+    #[test]
+    fn payloads_without_an_amount_are_skipped() {
+        assert_eq!(decode(&payload(None, Some("Apple Cash"))), None);
+        assert_eq!(decode(&payload(Some("https://apple.com/apple-cash"), None)), None);
+        assert_eq!(decode(b"not an archive"), None);
+    }
+
+    #[test]
+    fn totals_add_exactly() {
+        let payments: Vec<Payment> = (0..10)
+            .map(|_| payment("+15551234567", PaymentDirection::Received, 10))
+            .chain([
+                payment("+15551234567", PaymentDirection::Sent, 20),
+                payment("+15551234567", PaymentDirection::Requested, 500),
+                payment("bob@example.com", PaymentDirection::Sent, 5000),
+            ])
+            .collect();
+        let totals = totals(&payments);
+        assert_eq!(totals[0].contact.as_deref(), Some("bob@example.com"));
+        let ann = &totals[1];
+        assert_eq!((ann.received, ann.sent, ann.requested, ann.net), (100, 20, 500, 80));
+        assert_eq!(serde_json::to_value(ann).unwrap()["net"], "0.80");
+    }
+
+    #[test]
+    fn one_person_gets_one_total_row() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE message (ROWID INTEGER PRIMARY KEY, guid TEXT, date INTEGER, is_from_me INTEGER,
+                payload_data BLOB, handle_id INTEGER, balloon_bundle_id TEXT);
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+            CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER);
+            CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT, uncanonicalized_id TEXT);
+            INSERT INTO handle VALUES (1, '+15551234567', '(555) 123-4567'), (2, 'bob@example.com', NULL);
+            INSERT INTO chat_handle_join VALUES (1, 1), (2, 1), (2, 2);
+            "#,
+        )
+        .unwrap();
+        // Ann pays the user 25 and is paid 10 in their chat, and the user pays 7 in the group
+        let messages = [(1, 1, 0, 1, "$25"), (2, 1, 1, 0, "$10"), (3, 2, 1, 0, "$7")];
+        for (id, chat_id, is_from_me, handle_id, caption) in messages {
+            conn.execute(
+                "INSERT INTO message VALUES (?, ?, ?, ?, ?, ?, 'com.apple.PassbookUIService.PeerPaymentMessagesExtension')",
+                rusqlite::params![id, format!("m{}", id), id, is_from_me, payload(None, Some(caption)), handle_id],
+            )
+            .unwrap();
+            conn.execute("INSERT INTO chat_message_join VALUES (?, ?)", [chat_id, id]).unwrap();
+        }
+
+        let payments = query_payments(&conn, None, None, &Aliases::default()).unwrap();
+        let contacts: Vec<Option<&str>> = payments.iter().map(|p| p.contact.as_deref()).collect();
+        assert_eq!(contacts, vec![Some("+15551234567"), Some("+15551234567"), None]);
+
+        let totals = totals(&payments);
+        assert_eq!(totals.len(), 2);
+        let ann = &totals[0];
+        assert_eq!(ann.contact.as_deref(), Some("+15551234567"));
+        assert_eq!((ann.received, ann.sent, ann.net), (2500, 1000, 1500));
+    }
+
+    #[test]
+    fn totals_group_handle_spellings() {
+        let payments = [
+            payment("+1 (555) 123-4567", PaymentDirection::Sent, 1000),
+            payment("5551234567", PaymentDirection::Received, 2500),
+        ];
+        let totals = totals(&payments);
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].net, 1500);
+    }
+}
@@ -0,0 +1,159 @@
+// Search operators typed into the query box.
+//
+// `from:`, `has:` and `in:` tokens are taken out of the query and turned into
+// filters; whatever is left is searched as text. Values containing spaces can
+// be quoted, as in `in:"Book club"`. Regex queries are not parsed, since a
+// pattern may legitimately contain a colon.
+
+// Operator names with the description shown by autocomplete
+pub const OPERATORS: &[(&str, &str)] = &[
+    ("from:", "Messages from a contact, phone number, email or from:me"),
+    ("has:", "Messages with a link, attachment, location, contact or event"),
+    ("in:", "Messages in a named conversation"),
+];
+
+// Values accepted by has:, matching the attachment_type categories plus link and attachment,
+// and the shared locations, contact cards and invites parsed by attachment_data
+pub const HAS_VALUES: &[&str] = &["link", "attachment", "image", "video", "pdf", "audio", "location", "contact", "event"];
+
+#[derive(Debug, Default)]
+pub struct ParsedQuery {
+    // The query with operator tokens removed
+    pub text: String,
+    pub from: Vec<String>,
+    pub has: Vec<String>,
+    pub in_chat: Vec<String>,
+}
+
+// Byte ranges of whitespace-separated tokens, keeping quoted sections together
+fn token_ranges(query: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    let mut in_quotes = false;
+
+    for (i, c) in query.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        if c.is_whitespace() && !in_quotes {
+            if let Some(s) = start.take() {
+                ranges.push((s, i));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        ranges.push((s, query.len()));
+    }
+    ranges
+}
+
+/// Removes surrounding quotes from an operator value.
+pub fn unquote(value: &str) -> &str {
+    let value = value.strip_prefix('"').unwrap_or(value);
+    value.strip_suffix('"').unwrap_or(value)
+}
+
+/// Quotes an operator value if it contains whitespace.
+pub fn quote(value: &str) -> String {
+    if value.contains(char::is_whitespace) {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits a token into its operator name (without the colon) and raw value.
+pub fn split_operator(token: &str) -> Option<(&'static str, &str)> {
+    let colon = token.find(':')?;
+    let name = &token[..colon];
+    OPERATORS
+        .iter()
+        .map(|(op, _)| op.trim_end_matches(':'))
+        .find(|op| op.eq_ignore_ascii_case(name))
+        .map(|op| (op, &token[colon + 1..]))
+}
+
+pub fn parse(query: &str) -> ParsedQuery {
+    let mut parsed = ParsedQuery::default();
+    let mut text = String::with_capacity(query.len());
+    let mut copied_to = 0;
+
+    for (start, end) in token_ranges(query) {
+        let token = &query[start..end];
+        let value = match split_operator(token) {
+            Some((op, value)) => (op, unquote(value).trim()),
+            None => continue,
+        };
+        match value {
+            (_, "") => continue,
+            ("from", v) => parsed.from.push(v.to_string()),
+            ("has", v) if HAS_VALUES.contains(&v.to_lowercase().as_str()) => parsed.has.push(v.to_lowercase()),
+            ("in", v) => parsed.in_chat.push(v.to_string()),
+            _ => continue,
+        }
+        text.push_str(&query[copied_to..start]);
+        copied_to = end;
+    }
+    text.push_str(&query[copied_to..]);
+
+    parsed.text = if copied_to == 0 {
+        query.to_string()
+    } else {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    };
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_takes_operators_out_of_the_text() {
+        let parsed = parse("dinner from:me has:image tonight");
+        assert_eq!(parsed.text, "dinner tonight");
+        assert_eq!(parsed.from, vec!["me"]);
+        assert_eq!(parsed.has, vec!["image"]);
+        assert!(parsed.in_chat.is_empty());
+    }
+
+    #[test]
+    fn parse_reads_quoted_values() {
+        let parsed = parse(r#"in:"Book club" from:"Jane Doe" pizza"#);
+        assert_eq!(parsed.in_chat, vec!["Book club"]);
+        assert_eq!(parsed.from, vec!["Jane Doe"]);
+        assert_eq!(parsed.text, "pizza");
+    }
+
+    #[test]
+    fn parse_is_case_insensitive_for_operators() {
+        let parsed = parse("FROM:+15550100 Has:LINK");
+        assert_eq!(parsed.from, vec!["+15550100"]);
+        assert_eq!(parsed.has, vec!["link"]);
+        assert_eq!(parsed.text, "");
+    }
+
+    #[test]
+    fn parse_leaves_unknown_or_empty_operators_in_the_text() {
+        let parsed = parse("has:unicorn from: time 10:30");
+        assert!(parsed.has.is_empty());
+        assert!(parsed.from.is_empty());
+        assert_eq!(parsed.text, "has:unicorn from: time 10:30");
+    }
+
+    #[test]
+    fn parse_without_operators_keeps_the_query_as_typed() {
+        assert_eq!(parse("  two  spaces ").text, "  two  spaces ");
+    }
+
+    #[test]
+    fn quote_and_unquote_round_trip() {
+        assert_eq!(quote("Book club"), r#""Book club""#);
+        assert_eq!(quote("family"), "family");
+        assert_eq!(unquote(r#""Book club""#), "Book club");
+        assert_eq!(split_operator("In:family"), Some(("in", "family")));
+        assert_eq!(split_operator("http://x"), None);
+    }
+}
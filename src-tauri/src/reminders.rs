@@ -0,0 +1,287 @@
+// Follow-up reminders on messages.
+//
+// Reminders are keyed by message guid and stored in the app database. A
+// background thread checks for reminders that have come due, shows a desktop
+// notification once per reminder and emits a reminder-due event carrying the
+// message, so the UI can open it in its conversation with get_messages_around.
+use rusqlite::Connection;
+use serde::Serialize;
+use std::time::Duration;
+use tauri::Emitter;
+use tauri_plugin_notification::NotificationExt;
+
+use crate::{aliases, app_db, AppError, Message};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+// Characters of message text shown in a notification
+const NOTIFICATION_PREVIEW_CHARS: usize = 120;
+
+#[derive(Serialize, Debug)]
+pub struct Reminder {
+    id: i64,
+    message_guid: String,
+    due_at: i64,                 // Unix seconds
+    note: Option<String>,
+    created_at: i64,
+    notified_at: Option<i64>,
+    completed_at: Option<i64>,
+    is_due: bool,
+    // None if the message is no longer in chat.db
+    message: Option<Message>,
+}
+
+const REMINDER_COLUMNS: &str = "id, message_guid, due_at, note, created_at, notified_at, completed_at";
+
+// Open reminders are due from their due time until completed
+fn is_due(due_at: i64, completed_at: Option<i64>, now: i64) -> bool {
+    completed_at.is_none() && due_at <= now
+}
+
+fn reminder_from_row(row: &rusqlite::Row) -> rusqlite::Result<Reminder> {
+    let due_at: i64 = row.get(2)?;
+    let completed_at: Option<i64> = row.get(6)?;
+    Ok(Reminder {
+        id: row.get(0)?,
+        message_guid: row.get(1)?,
+        due_at,
+        note: row.get(3)?,
+        created_at: row.get(4)?,
+        notified_at: row.get(5)?,
+        completed_at,
+        is_due: is_due(due_at, completed_at, app_db::now()),
+        message: None,
+    })
+}
+
+// Fills in the flagged message, when chat.db can be read
+fn attach_messages(reminders: &mut [Reminder]) {
+    let conn = match crate::open_imessage_db() {
+        Ok(conn) => conn,
+        Err(e) => {
+            log::warn!("Could not open chat.db for reminders: {}", e);
+            return;
+        }
+    };
+    let aliases = aliases::Aliases::load();
+    for reminder in reminders {
+        match crate::message_by_guid(&conn, &reminder.message_guid) {
+            Ok(mut message) => {
+                if let Some(message) = &mut message {
+                    aliases.apply(message);
+                }
+                reminder.message = message;
+            },
+            Err(e) => log::warn!("Could not load message {}: {}", reminder.message_guid, e),
+        }
+    }
+}
+
+fn load_reminder(conn: &Connection, message_guid: &str) -> Result<Reminder, AppError> {
+    let sql = format!("SELECT {} FROM reminder WHERE message_guid = ?", REMINDER_COLUMNS);
+    let mut reminder = conn.query_row(&sql, [message_guid], reminder_from_row)?;
+    attach_messages(std::slice::from_mut(&mut reminder));
+    Ok(reminder)
+}
+
+/// Flags a message for follow-up, replacing any existing reminder on it.
+#[tauri::command]
+pub async fn set_reminder(message_guid: String, due_at: i64, note: Option<String>) -> Result<Reminder, AppError> {
+    let chat_conn = crate::open_imessage_db()?;
+    if crate::message_by_guid(&chat_conn, &message_guid)?.is_none() {
+        return Err(AppError::OtherError(format!("Message {} not found", message_guid)));
+    }
+
+    let conn = app_db::open_app_db()?;
+    save_reminder(&conn, &message_guid, due_at, note, app_db::now())?;
+    load_reminder(&conn, &message_guid)
+}
+
+// Setting a reminder again reopens it, so it notifies at the new time
+fn save_reminder(conn: &Connection, message_guid: &str, due_at: i64, note: Option<String>, now: i64) -> Result<(), AppError> {
+    let note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    conn.execute(
+        r#"
+        INSERT INTO reminder (message_guid, due_at, note, created_at) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(message_guid) DO UPDATE SET
+            due_at = excluded.due_at,
+            note = excluded.note,
+            notified_at = NULL,
+            completed_at = NULL
+        "#,
+        rusqlite::params![message_guid, due_at, note, now],
+    )?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn complete_reminder(message_guid: String) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    complete(&conn, &message_guid, app_db::now())
+}
+
+fn complete(conn: &Connection, message_guid: &str, now: i64) -> Result<(), AppError> {
+    conn.execute(
+        "UPDATE reminder SET completed_at = ? WHERE message_guid = ?",
+        rusqlite::params![now, message_guid],
+    )?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_reminder(message_guid: String) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    conn.execute("DELETE FROM reminder WHERE message_guid = ?", [message_guid])?;
+    Ok(())
+}
+
+/// Open reminders ordered by due date. With `due_only`, just those due or overdue.
+#[tauri::command]
+pub async fn list_reminders(due_only: bool) -> Result<Vec<Reminder>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut reminders = open_reminders(&conn, due_only.then(app_db::now))?;
+    attach_messages(&mut reminders);
+    Ok(reminders)
+}
+
+// Reminders not yet completed, only those due by `due_by` when given
+fn open_reminders(conn: &Connection, due_by: Option<i64>) -> Result<Vec<Reminder>, AppError> {
+    let mut sql = format!("SELECT {} FROM reminder WHERE completed_at IS NULL", REMINDER_COLUMNS);
+    if due_by.is_some() {
+        sql.push_str(" AND due_at <= ?1");
+    }
+    sql.push_str(" ORDER BY due_at");
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = match due_by {
+        Some(due_by) => stmt.query_map([due_by], reminder_from_row)?,
+        None => stmt.query_map([], reminder_from_row)?,
+    };
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+// Due reminders that haven't been notified yet
+fn unnotified_due(conn: &Connection, now: i64) -> Result<Vec<Reminder>, AppError> {
+    let sql = format!(
+        "SELECT {} FROM reminder WHERE completed_at IS NULL AND notified_at IS NULL AND due_at <= ? ORDER BY due_at",
+        REMINDER_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let due = stmt.query_map([now], reminder_from_row)?.collect::<Result<Vec<_>, _>>()?;
+    Ok(due)
+}
+
+fn preview(text: &str) -> String {
+    let mut preview: String = text.chars().take(NOTIFICATION_PREVIEW_CHARS).collect();
+    if text.chars().count() > NOTIFICATION_PREVIEW_CHARS {
+        preview.push('…');
+    }
+    preview
+}
+
+// Notifies about reminders that came due since the last check
+fn notify_due(app: &tauri::AppHandle) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut due = unnotified_due(&conn, app_db::now())?;
+    if due.is_empty() {
+        return Ok(());
+    }
+    attach_messages(&mut due);
+
+    for reminder in due {
+        let title = match reminder.message.as_ref().and_then(|m| m.conversation_name.clone().or(m.sender_name.clone())) {
+            Some(name) => format!("Follow up: {}", name),
+            None => "Follow up".to_string(),
+        };
+        let body = match (&reminder.note, &reminder.message) {
+            (Some(note), _) => note.clone(),
+            (None, Some(message)) => preview(&message.text),
+            (None, None) => "A flagged message is due".to_string(),
+        };
+
+        if let Err(e) = app.notification().builder().title(title).body(body).show() {
+            log::warn!("Failed to show reminder notification: {}", e);
+        }
+        if let Err(e) = app.emit("reminder-due", &reminder) {
+            log::warn!("Failed to emit reminder-due: {}", e);
+        }
+
+        mark_notified(&conn, reminder.id, app_db::now())?;
+    }
+    Ok(())
+}
+
+fn mark_notified(conn: &Connection, id: i64, now: i64) -> Result<(), AppError> {
+    conn.execute("UPDATE reminder SET notified_at = ? WHERE id = ?", rusqlite::params![now, id])?;
+    Ok(())
+}
+
+/// Checks for due reminders now and then every minute.
+pub fn spawn_poller(app: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = notify_due(&app) {
+            log::error!("Failed to check reminders: {}", e);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guids(reminders: &[Reminder]) -> Vec<&str> {
+        reminders.iter().map(|r| r.message_guid.as_str()).collect()
+    }
+
+    #[test]
+    fn due_from_due_time_until_completed() {
+        assert!(!is_due(100, None, 99));
+        assert!(is_due(100, None, 100));
+        assert!(is_due(100, None, 5000));
+        assert!(!is_due(100, Some(150), 200));
+    }
+
+    #[test]
+    fn notifies_each_due_reminder_once() {
+        let conn = app_db::open_test_db();
+        save_reminder(&conn, "early", 100, None, 0).unwrap();
+        save_reminder(&conn, "late", 300, Some("  call back ".to_string()), 0).unwrap();
+
+        let due = unnotified_due(&conn, 200).unwrap();
+        assert_eq!(guids(&due), vec!["early"]);
+        mark_notified(&conn, due[0].id, 200).unwrap();
+        assert!(unnotified_due(&conn, 250).unwrap().is_empty());
+
+        let due = unnotified_due(&conn, 300).unwrap();
+        assert_eq!(guids(&due), vec!["late"]);
+        assert_eq!(due[0].note.as_deref(), Some("call back"));
+    }
+
+    #[test]
+    fn completed_reminders_are_closed_until_set_again() {
+        let conn = app_db::open_test_db();
+        save_reminder(&conn, "a", 100, None, 0).unwrap();
+        save_reminder(&conn, "b", 500, None, 0).unwrap();
+        complete(&conn, "a", 150).unwrap();
+
+        assert!(unnotified_due(&conn, 200).unwrap().is_empty());
+        assert_eq!(guids(&open_reminders(&conn, None).unwrap()), vec!["b"]);
+        assert!(open_reminders(&conn, Some(200)).unwrap().is_empty());
+
+        // Setting it again reopens it at the new time
+        let id = conn.query_row("SELECT id FROM reminder WHERE message_guid = 'a'", [], |row| row.get(0)).unwrap();
+        mark_notified(&conn, id, 120).unwrap();
+        save_reminder(&conn, "a", 400, None, 200).unwrap();
+        assert_eq!(guids(&open_reminders(&conn, None).unwrap()), vec!["a", "b"]);
+        assert_eq!(guids(&unnotified_due(&conn, 400).unwrap()), vec!["a"]);
+    }
+
+    #[test]
+    fn previews_are_shortened() {
+        assert_eq!(preview("short"), "short");
+        let long = "x".repeat(NOTIFICATION_PREVIEW_CHARS + 5);
+        assert_eq!(preview(&long).chars().count(), NOTIFICATION_PREVIEW_CHARS + 1);
+        assert!(preview(&long).ends_with('…'));
+    }
+}
@@ -0,0 +1,222 @@
+// Named searches the user runs again and again, shown as smart folders.
+//
+// A saved search stores its SearchParams as JSON in the app database. Its date
+// range can be relative ("this month", "last 7 days"), in which case the fixed
+// start and end dates are filled in each time it runs. The newest chat.db
+// message at the time of each run is remembered so the next run can report how
+// many hits arrived since.
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::app_db;
+use crate::vocabulary::VocabularyState;
+use crate::{AppError, SearchParams, SearchResult};
+
+// Date range that moves with the current day
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RelativeDateRange {
+    Today,
+    ThisWeek,
+    ThisMonth,
+    ThisYear,
+    LastDays { days: u32 },
+}
+
+impl RelativeDateRange {
+    // First local day included in the range
+    fn first_day(&self, today: NaiveDate) -> NaiveDate {
+        match self {
+            RelativeDateRange::Today => today,
+            RelativeDateRange::ThisWeek => today - Duration::days(today.weekday().num_days_from_monday() as i64),
+            RelativeDateRange::ThisMonth => today.with_day(1).unwrap_or(today),
+            RelativeDateRange::ThisYear => today.with_ordinal(1).unwrap_or(today),
+            RelativeDateRange::LastDays { days } => today - Duration::days((*days).max(1) as i64 - 1),
+        }
+    }
+
+    /// Sets fixed dates on `params` for the range as of `today`.
+    pub fn apply(&self, params: &mut SearchParams, today: NaiveDate) {
+        // The start_date filter only matches messages after the end of that day,
+        // so pass the day before the range begins
+        let start = self.first_day(today) - Duration::days(1);
+        params.start_date = Some(start.format("%Y-%m-%d").to_string());
+        params.end_date = None;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedSearch {
+    id: i64,
+    name: String,
+    params: SearchParams,
+    // Overrides start_date and end_date in params when set
+    date_range: Option<RelativeDateRange>,
+    created_at: i64,
+    updated_at: i64,
+    last_run_at: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SavedSearchRun {
+    saved_search: SavedSearch,
+    result: SearchResult,
+    // Hits in messages that arrived since the previous run; None on the first run
+    new_since_last_run: Option<usize>,
+}
+
+fn saved_search_from_row(row: &rusqlite::Row) -> rusqlite::Result<SavedSearch> {
+    let params_json: String = row.get(2)?;
+    let date_range_json: Option<String> = row.get(3)?;
+    let to_sql_error = |e: serde_json::Error| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e));
+
+    Ok(SavedSearch {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        params: serde_json::from_str(&params_json).map_err(to_sql_error)?,
+        date_range: match date_range_json {
+            Some(json) => Some(serde_json::from_str(&json).map_err(to_sql_error)?),
+            None => None,
+        },
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+        last_run_at: row.get(6)?,
+    })
+}
+
+const SAVED_SEARCH_COLUMNS: &str = "id, name, params, date_range, created_at, updated_at, last_run_at";
+
+fn load_saved_search(conn: &Connection, id: i64) -> Result<SavedSearch, AppError> {
+    let sql = format!("SELECT {} FROM saved_search WHERE id = ?", SAVED_SEARCH_COLUMNS);
+    conn.query_row(&sql, [id], saved_search_from_row)
+        .optional()?
+        .ok_or_else(|| AppError::OtherError(format!("Saved search {} not found", id)))
+}
+
+fn validate_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::OtherError("Saved search name cannot be empty".to_string()));
+    }
+    Ok(name.to_string())
+}
+
+#[tauri::command]
+pub async fn create_saved_search(
+    name: String,
+    params: SearchParams,
+    date_range: Option<RelativeDateRange>,
+) -> Result<SavedSearch, AppError> {
+    let name = validate_name(&name)?;
+    let conn = app_db::open_app_db()?;
+    let now = app_db::now();
+
+    conn.execute(
+        "INSERT INTO saved_search (name, params, date_range, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+        rusqlite::params![
+            name,
+            serde_json::to_string(&params)?,
+            date_range.map(|r| serde_json::to_string(&r)).transpose()?,
+            now,
+            now,
+        ],
+    )?;
+
+    load_saved_search(&conn, conn.last_insert_rowid())
+}
+
+#[tauri::command]
+pub async fn update_saved_search(
+    id: i64,
+    name: String,
+    params: SearchParams,
+    date_range: Option<RelativeDateRange>,
+) -> Result<SavedSearch, AppError> {
+    let name = validate_name(&name)?;
+    let conn = app_db::open_app_db()?;
+
+    let updated = conn.execute(
+        "UPDATE saved_search SET name = ?, params = ?, date_range = ?, updated_at = ? WHERE id = ?",
+        rusqlite::params![
+            name,
+            serde_json::to_string(&params)?,
+            date_range.map(|r| serde_json::to_string(&r)).transpose()?,
+            app_db::now(),
+            id,
+        ],
+    )?;
+    if updated == 0 {
+        return Err(AppError::OtherError(format!("Saved search {} not found", id)));
+    }
+
+    load_saved_search(&conn, id)
+}
+
+#[tauri::command]
+pub async fn delete_saved_search(id: i64) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    conn.execute("DELETE FROM saved_search WHERE id = ?", [id])?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_saved_searches() -> Result<Vec<SavedSearch>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let sql = format!("SELECT {} FROM saved_search ORDER BY name COLLATE NOCASE", SAVED_SEARCH_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    let searches = stmt
+        .query_map([], saved_search_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(searches)
+}
+
+// Runs the saved search through search_messages, so results stream the same way
+#[tauri::command]
+pub async fn run_saved_search(app: tauri::AppHandle, id: i64) -> Result<SavedSearchRun, AppError> {
+    let app_conn = app_db::open_app_db()?;
+    let saved_search = load_saved_search(&app_conn, id)?;
+    let last_seen_message_id: Option<i64> = app_conn.query_row(
+        "SELECT last_seen_message_id FROM saved_search WHERE id = ?",
+        [id],
+        |row| row.get(0),
+    )?;
+
+    let mut params = saved_search.params.clone();
+    if let Some(range) = saved_search.date_range {
+        range.apply(&mut params, Local::now().date_naive());
+    }
+    log::info!("Running saved search {} ({})", id, saved_search.name);
+
+    let chat_conn = crate::open_imessage_db()?;
+    let newest_message_id: Option<i64> = chat_conn.query_row("SELECT MAX(ROWID) FROM message", [], |row| row.get(0))?;
+    drop(chat_conn);
+    let new_since_last_run = match last_seen_message_id {
+        Some(last_seen) => {
+            let (app, params) = (app.clone(), params.clone());
+            Some(
+                crate::run_blocking(move || {
+                    let conn = crate::open_imessage_db()?;
+                    crate::count_search_hits_after(&conn, &params, &app.state::<VocabularyState>(), last_seen)
+                })
+                .await?,
+            )
+        },
+        None => None,
+    };
+
+    let result = crate::run_search(app, params).await?;
+
+    let now = app_db::now();
+    app_conn.execute(
+        "UPDATE saved_search SET last_run_at = ?, last_seen_message_id = ? WHERE id = ?",
+        rusqlite::params![now, newest_message_id, id],
+    )?;
+
+    Ok(SavedSearchRun {
+        saved_search: SavedSearch { last_run_at: Some(now), ..saved_search },
+        result,
+        new_since_last_run,
+    })
+}
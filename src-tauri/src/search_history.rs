@@ -0,0 +1,206 @@
+// Recently run searches, kept in the app database.
+//
+// Searches usually run on every keystroke, so an entry made within a few
+// seconds of the previous one that extends or shortens its query, with the
+// same filters, replaces it instead of adding a new row. Recording can be
+// switched off with the history_enabled setting, which also clears what was
+// stored.
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::app_db;
+use crate::{AppError, SearchParams, SearchResult};
+
+const HISTORY_ENABLED_SETTING: &str = "history_enabled";
+
+// Entries kept before the oldest are dropped
+const MAX_HISTORY_ENTRIES: i64 = 500;
+
+// Searches this close together are treated as one query being typed
+const TYPING_MERGE_SECONDS: i64 = 10;
+
+#[derive(Serialize, Debug)]
+pub struct SearchHistoryEntry {
+    id: i64,
+    query: String,
+    params: SearchParams,
+    searched_at: i64,
+}
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<SearchHistoryEntry> {
+    let params_json: String = row.get(2)?;
+    Ok(SearchHistoryEntry {
+        id: row.get(0)?,
+        query: row.get(1)?,
+        params: serde_json::from_str(&params_json).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        searched_at: row.get(3)?,
+    })
+}
+
+// Search parameters other than the query text, for telling apart searches of
+// the same query with different filters
+fn filters_of(params_json: &str) -> Option<serde_json::Value> {
+    let mut params: serde_json::Value = serde_json::from_str(params_json).ok()?;
+    params.as_object_mut()?.remove("query");
+    Some(params)
+}
+
+pub fn is_enabled(conn: &Connection) -> Result<bool, AppError> {
+    Ok(app_db::get_setting(conn, HISTORY_ENABLED_SETTING)?.as_deref() != Some("false"))
+}
+
+/// Adds a search to the history unless history is turned off.
+pub fn record(params: &SearchParams) -> Result<(), AppError> {
+    let query = params.query.trim();
+    if query.is_empty() {
+        return Ok(());
+    }
+
+    let conn = app_db::open_app_db()?;
+    if !is_enabled(&conn)? {
+        return Ok(());
+    }
+
+    let now = app_db::now();
+    let params_json = serde_json::to_string(params)?;
+
+    // Replace the previous entry while the user is still typing the same query
+    let last: Option<(i64, String, String, i64)> = conn
+        .query_row(
+            "SELECT id, query, params, searched_at FROM search_history ORDER BY searched_at DESC, id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .ok();
+    if let Some((id, last_query, last_params_json, searched_at)) = last {
+        let same_filters = filters_of(&last_params_json) == filters_of(&params_json);
+        let same_query = same_filters && (query.starts_with(last_query.as_str()) || last_query.starts_with(query));
+        if same_query && now - searched_at <= TYPING_MERGE_SECONDS {
+            conn.execute(
+                "UPDATE search_history SET query = ?, params = ?, searched_at = ? WHERE id = ?",
+                rusqlite::params![query, params_json, now, id],
+            )?;
+            return Ok(());
+        }
+    }
+
+    // Re-running an identical search moves it to the top instead of duplicating it
+    conn.execute("DELETE FROM search_history WHERE params = ?", [&params_json])?;
+    conn.execute(
+        "INSERT INTO search_history (query, params, searched_at) VALUES (?, ?, ?)",
+        rusqlite::params![query, params_json, now],
+    )?;
+    conn.execute(
+        "DELETE FROM search_history WHERE id NOT IN (SELECT id FROM search_history ORDER BY searched_at DESC, id DESC LIMIT ?)",
+        [MAX_HISTORY_ENTRIES],
+    )?;
+    Ok(())
+}
+
+/// Distinct past queries starting with `prefix` (case-insensitive), most recent first.
+pub fn completions(conn: &Connection, prefix: &str, limit: usize) -> Result<Vec<String>, AppError> {
+    let prefix = prefix.trim();
+    if prefix.is_empty() || !is_enabled(conn)? {
+        return Ok(Vec::new());
+    }
+
+    let mut stmt = conn.prepare(
+        r#"
+        SELECT query FROM search_history
+        WHERE substr(lower(query), 1, length(?1)) = lower(?1)
+        GROUP BY lower(query)
+        ORDER BY MAX(searched_at) DESC
+        LIMIT ?2
+        "#,
+    )?;
+    let queries = stmt
+        .query_map(rusqlite::params![prefix, limit as i64], |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(queries)
+}
+
+#[tauri::command]
+pub async fn list_search_history(limit: Option<usize>) -> Result<Vec<SearchHistoryEntry>, AppError> {
+    let conn = app_db::open_app_db()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, query, params, searched_at FROM search_history ORDER BY searched_at DESC, id DESC LIMIT ?",
+    )?;
+    let limit = limit.map(|l| l as i64).unwrap_or(MAX_HISTORY_ENTRIES);
+    let entries = stmt
+        .query_map([limit], entry_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(entries)
+}
+
+#[tauri::command]
+pub async fn rerun_search_history(app: tauri::AppHandle, id: i64) -> Result<SearchResult, AppError> {
+    let conn = app_db::open_app_db()?;
+    let entry = conn.query_row(
+        "SELECT id, query, params, searched_at FROM search_history WHERE id = ?",
+        [id],
+        entry_from_row,
+    )?;
+    drop(conn);
+
+    let result = crate::run_search(app, entry.params.clone()).await?;
+    if let Err(e) = record(&entry.params) {
+        log::warn!("Failed to record search history: {}", e);
+    }
+    Ok(result)
+}
+
+#[tauri::command]
+pub async fn delete_search_history_entry(id: i64) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    conn.execute("DELETE FROM search_history WHERE id = ?", [id])?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn clear_search_history() -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    conn.execute("DELETE FROM search_history", [])?;
+    // Reclaim the pages so cleared queries don't linger in the file
+    conn.execute_batch("VACUUM")?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_history_enabled() -> Result<bool, AppError> {
+    let conn = app_db::open_app_db()?;
+    is_enabled(&conn)
+}
+
+#[tauri::command]
+pub async fn set_history_enabled(enabled: bool) -> Result<(), AppError> {
+    let conn = app_db::open_app_db()?;
+    app_db::set_setting(&conn, HISTORY_ENABLED_SETTING, if enabled { "true" } else { "false" })?;
+    if !enabled {
+        conn.execute("DELETE FROM search_history", [])?;
+        conn.execute_batch("VACUUM")?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn search_history_completions(prefix: String, limit: Option<usize>) -> Result<Vec<String>, AppError> {
+    let conn = app_db::open_app_db()?;
+    completions(&conn, &prefix, limit.unwrap_or(10))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_of_ignores_only_the_query() {
+        let typed = r#"{"query":"din","sort_direction":"desc","attachment_type":"all"}"#;
+        let extended = r#"{"query":"dinner","sort_direction":"desc","attachment_type":"all"}"#;
+        let filtered = r#"{"query":"dinner","sort_direction":"desc","attachment_type":"image"}"#;
+        assert_eq!(filters_of(typed), filters_of(extended));
+        assert_ne!(filters_of(extended), filters_of(filtered));
+        assert_eq!(filters_of("not json"), None);
+    }
+}
@@ -0,0 +1,87 @@
+// Bookkeeping for running searches.
+//
+// Each call to search_messages registers a job with an id. Starting a new
+// search cancels every older one, so only the latest search delivers results
+// to the UI, and cancel_search can stop a job explicitly. Cancelling interrupts
+// the job's SQLite connection and sets a flag checked between result rows.
+use rusqlite::{Connection, InterruptHandle};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct RunningSearch {
+    interrupt: InterruptHandle,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl RunningSearch {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.interrupt.interrupt();
+    }
+}
+
+// Managed Tauri state shared by search_messages and cancel_search
+#[derive(Default)]
+pub struct SearchJobs {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, RunningSearch>>,
+}
+
+// A registered search; it is unregistered when dropped
+pub struct SearchJob<'a> {
+    jobs: &'a SearchJobs,
+    pub id: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl SearchJob<'_> {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for SearchJob<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.jobs.running.lock() {
+            running.remove(&self.id);
+        }
+    }
+}
+
+impl SearchJobs {
+    /// Registers a search running on `conn` and cancels all older searches.
+    pub fn start(&self, conn: &Connection) -> SearchJob<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        for (old_id, search) in running.drain() {
+            log::info!("Cancelling search {} superseded by search {}", old_id, id);
+            search.cancel();
+        }
+        running.insert(id, RunningSearch {
+            interrupt: conn.get_interrupt_handle(),
+            cancelled: cancelled.clone(),
+        });
+
+        SearchJob { jobs: self, id, cancelled }
+    }
+
+    /// Cancels the search with `id`. Returns false if it is no longer running.
+    pub fn cancel(&self, id: u64) -> bool {
+        let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        match running.get(&id) {
+            Some(search) => {
+                search.cancel();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn cancel_search(id: u64, jobs: tauri::State<'_, SearchJobs>) -> Result<bool, crate::AppError> {
+    Ok(jobs.cancel(id))
+}
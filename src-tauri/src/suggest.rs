@@ -0,0 +1,411 @@
+// Autocomplete for the search box.
+//
+// Contacts, handles, conversation names and frequent words are loaded at
+// startup into a sorted list of folded keys, so completing a prefix is a binary
+// search plus a short scan. The list is rebuilt in the background whenever
+// chat.db or the local names and people in the app database change.
+// Operators and search history are matched directly since there are only a
+// handful of each. Every key starts at a word boundary, which lets "doe"
+// find "Jane Doe".
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+use tauri::Manager;
+
+use crate::aliases::Aliases;
+use crate::app_db::AppDbState;
+use crate::vocabulary::VocabularyState;
+use crate::{contacts, query, search_history, text, AppError};
+
+// Words must occur this often to be offered as completions
+const MIN_TERM_COUNT: u32 = 3;
+
+const DEFAULT_SUGGESTIONS: usize = 10;
+
+// How often chat.db is checked for changes that call for a rebuild
+const REFRESH_INTERVAL: Duration = Duration::from_secs(120);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionKind {
+    Contact,
+    Handle,
+    Conversation,
+    Operator,
+    Term,
+    History,
+}
+
+impl SuggestionKind {
+    // Kinds are ranked ahead of each other; frequency only orders within a kind
+    fn rank(self) -> f64 {
+        match self {
+            SuggestionKind::History => 5.0,
+            SuggestionKind::Operator => 4.0,
+            SuggestionKind::Contact | SuggestionKind::Conversation => 3.0,
+            SuggestionKind::Handle => 2.0,
+            SuggestionKind::Term => 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Suggestion {
+    kind: SuggestionKind,
+    label: String,
+    // Full query text to put in the search box when picked
+    value: String,
+    detail: Option<String>,
+}
+
+struct Entry {
+    kind: SuggestionKind,
+    label: String,
+    folded_label: String,
+    // Replaces the token being typed
+    value: String,
+    detail: Option<String>,
+    count: u32,
+}
+
+#[derive(Default)]
+pub struct SuggestIndex {
+    entries: Vec<Entry>,
+    // Folded keys sorted for prefix lookup, each pointing at an entry
+    keys: Vec<(String, u32)>,
+}
+
+// Managed Tauri state; None until the first build finishes
+#[derive(Default)]
+pub struct SuggestIndexState(pub RwLock<Option<SuggestIndex>>);
+
+impl SuggestIndex {
+    fn add(&mut self, kind: SuggestionKind, label: String, value: String, detail: Option<String>, count: u32) {
+        let id = self.entries.len() as u32;
+        let folded_label = text::fold_text(&label);
+
+        // One key per word start, so any word of the label can be typed
+        let mut previous_alphanumeric = false;
+        for (i, c) in folded_label.char_indices() {
+            let alphanumeric = c.is_alphanumeric();
+            if alphanumeric && !previous_alphanumeric {
+                self.keys.push((folded_label[i..].to_string(), id));
+            }
+            previous_alphanumeric = alphanumeric;
+        }
+        if let Some(detail) = &detail {
+            self.keys.push((text::fold_text(detail), id));
+        }
+        self.entries.push(Entry { kind, label, folded_label, value, detail, count });
+    }
+
+    /// Builds the index from chat.db, the AddressBook and the vocabulary.
+    pub fn build(conn: &Connection, vocabulary: &VocabularyState) -> Result<Self, AppError> {
+        let mut index = SuggestIndex::default();
+        let contacts = contacts::directory();
+        let aliases = Aliases::load();
+
+        // Handles the user has messages with, named by local name or contact.
+        // Handles linked to a person are offered once, under the person's name.
+        let mut stmt = conn.prepare(
+            "SELECT h.id, COUNT(m.ROWID) FROM handle h JOIN message m ON m.handle_id = h.ROWID GROUP BY h.id",
+        )?;
+        let handles = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut people: HashMap<String, u32> = HashMap::new();
+        for (handle, count) in handles {
+            let count = count as u32;
+            if let Some(person) = aliases.person_name(&handle) {
+                *people.entry(person.to_string()).or_insert(0) += count;
+                continue;
+            }
+            let value = format!("from:{}", handle);
+            match aliases.display_name(&handle).or_else(|| contacts.name(&handle)) {
+                Some(name) => index.add(SuggestionKind::Contact, name.to_string(), value, Some(handle), count),
+                None => index.add(SuggestionKind::Handle, handle, value, None, count),
+            }
+        }
+        for (name, count) in people {
+            let value = format!("from:{}", query::quote(&name));
+            index.add(SuggestionKind::Contact, name, value, None, count);
+        }
+
+        // Named conversations, mostly group chats
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT c.display_name, COUNT(cmj.message_id)
+            FROM chat c
+            LEFT JOIN chat_message_join cmj ON cmj.chat_id = c.ROWID
+            WHERE c.display_name IS NOT NULL AND c.display_name != ''
+            GROUP BY c.ROWID
+            "#,
+        )?;
+        let conversations = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (name, count) in conversations {
+            let value = format!("in:{}", query::quote(&name));
+            index.add(SuggestionKind::Conversation, name, value, None, count as u32);
+        }
+
+        // Frequent words from the user's messages
+        let mut vocabulary = vocabulary.0.lock().map_err(|e| AppError::OtherError(e.to_string()))?;
+        vocabulary.update(conn)?;
+        for (word, count) in vocabulary.frequent_words(MIN_TERM_COUNT) {
+            index.add(SuggestionKind::Term, word.to_string(), word.to_string(), None, count);
+        }
+
+        index.keys.sort_unstable();
+        Ok(index)
+    }
+
+    // Entries with a key starting with `folded_prefix`, each once
+    fn lookup(&self, folded_prefix: &str) -> Vec<&Entry> {
+        let start = self.keys.partition_point(|(key, _)| key.as_str() < folded_prefix);
+        let mut ids: Vec<u32> = self.keys[start..]
+            .iter()
+            .take_while(|(key, _)| key.starts_with(folded_prefix))
+            .map(|&(_, id)| id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter().map(|id| &self.entries[id as usize]).collect()
+    }
+
+    fn of_kinds(&self, kinds: &[SuggestionKind]) -> Vec<&Entry> {
+        self.entries.iter().filter(|e| kinds.contains(&e.kind)).collect()
+    }
+}
+
+// Latest modification of chat.db or its write-ahead log, which new messages touch
+fn sources_modified() -> Option<SystemTime> {
+    let dir = dirs::home_dir()?.join("Library/Messages");
+    [dir.join("chat.db"), dir.join("chat.db-wal")]
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+// Contents of the app tables the index reads. The app database file changes
+// on every search, so its modification time can't tell when to rebuild.
+fn aliases_version(conn: &Connection) -> Result<String, AppError> {
+    Ok(conn.query_row(
+        r#"
+        SELECT
+            COALESCE((SELECT group_concat(row, ',') FROM (
+                SELECT quote(id) || quote(name) AS row FROM person ORDER BY id)), '')
+            || '/' ||
+            COALESCE((SELECT group_concat(row, ',') FROM (
+                SELECT quote(handle_key) || quote(display_name) || quote(person_id) AS row
+                FROM handle_alias ORDER BY handle_key)), '')
+        "#,
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+fn build_and_store(app: &tauri::AppHandle) -> bool {
+    let started = Instant::now();
+    let result = crate::open_imessage_db().and_then(|conn| {
+        let vocabulary = app.state::<VocabularyState>();
+        SuggestIndex::build(&conn, &vocabulary)
+    });
+    match result {
+        Ok(index) => {
+            log::info!("Built autocomplete index with {} keys in {:?}", index.keys.len(), started.elapsed());
+            let state = app.state::<SuggestIndexState>();
+            match state.0.write() {
+                Ok(mut slot) => *slot = Some(index),
+                Err(e) => log::error!("Autocomplete index lock poisoned: {}", e),
+            };
+            true
+        },
+        Err(e) => {
+            log::error!("Failed to build autocomplete index: {}", e);
+            false
+        },
+    }
+}
+
+/// Builds the index on a background thread so startup isn't blocked, then
+/// rebuilds it whenever chat.db or the local names have changed since the
+/// last build.
+pub fn spawn_build(app: tauri::AppHandle) {
+    std::thread::spawn(move || {
+        // Modification time and aliases the current index was built from
+        let mut built_from = None;
+        loop {
+            let aliases = app.state::<AppDbState>().with(aliases_version).unwrap_or_else(|e| {
+                log::warn!("Failed to read aliases version: {}", e);
+                String::new()
+            });
+            let sources = Some((sources_modified(), aliases));
+            if built_from != sources && build_and_store(&app) {
+                built_from = sources;
+            }
+            std::thread::sleep(REFRESH_INTERVAL);
+        }
+    });
+}
+
+// Splits the query being typed into what comes before the last token and the token itself
+fn split_last_token(prefix: &str) -> (&str, &str) {
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in prefix.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c.is_whitespace() && !in_quotes {
+            start = i + c.len_utf8();
+        }
+    }
+    prefix.split_at(start)
+}
+
+/// Ranked completions for the query typed so far.
+#[tauri::command]
+pub async fn suggest(
+    prefix: String,
+    limit: Option<usize>,
+    index: tauri::State<'_, SuggestIndexState>,
+    app_db: tauri::State<'_, AppDbState>,
+) -> Result<Vec<Suggestion>, AppError> {
+    let limit = limit.unwrap_or(DEFAULT_SUGGESTIONS);
+    let (head, token) = split_last_token(&prefix);
+    let mut scored: Vec<(f64, Suggestion)> = Vec::new();
+
+    let mut push = |kind: SuggestionKind, label: &str, value: String, detail: Option<String>, count: u32, exact_start: bool| {
+        let score = kind.rank() * 10.0 + (1.0 + count as f64).ln() + if exact_start { 3.0 } else { 0.0 };
+        scored.push((score, Suggestion { kind, label: label.to_string(), value, detail }));
+    };
+
+    let index = index.0.read().map_err(|e| AppError::OtherError(e.to_string()))?;
+
+    match query::split_operator(token) {
+        // Completing an operator value
+        Some((op, value)) => {
+            let folded_value = text::fold_text(query::unquote(value));
+            match op {
+                "has" => {
+                    for has in query::HAS_VALUES.iter().filter(|v| v.starts_with(folded_value.as_str())) {
+                        push(SuggestionKind::Operator, &format!("has:{}", has), format!("{}has:{}", head, has), None, 0, true);
+                    }
+                },
+                _ => {
+                    let kinds: &[SuggestionKind] = if op == "from" {
+                        if "me".starts_with(folded_value.as_str()) {
+                            push(SuggestionKind::Operator, "from:me", format!("{}from:me", head), None, 0, true);
+                        }
+                        &[SuggestionKind::Contact, SuggestionKind::Handle]
+                    } else {
+                        &[SuggestionKind::Conversation]
+                    };
+                    if let Some(index) = index.as_ref() {
+                        let entries = if folded_value.is_empty() {
+                            index.of_kinds(kinds)
+                        } else {
+                            index.lookup(&folded_value)
+                        };
+                        for entry in entries.into_iter().filter(|e| kinds.contains(&e.kind)) {
+                            let exact_start = entry.folded_label.starts_with(folded_value.as_str());
+                            push(entry.kind, &entry.label, format!("{}{}", head, entry.value), entry.detail.clone(), entry.count, exact_start);
+                        }
+                    }
+                },
+            }
+        },
+        None => {
+            let folded_token = text::fold_text(token);
+            if !folded_token.is_empty() {
+                for (op, description) in query::OPERATORS {
+                    if op.starts_with(folded_token.as_str()) {
+                        push(SuggestionKind::Operator, op, format!("{}{}", head, op), Some(description.to_string()), 0, true);
+                    }
+                }
+                if let Some(index) = index.as_ref() {
+                    for entry in index.lookup(&folded_token) {
+                        if entry.kind == SuggestionKind::Term && entry.folded_label == folded_token {
+                            continue;
+                        }
+                        let exact_start = entry.folded_label.starts_with(folded_token.as_str());
+                        push(entry.kind, &entry.label, format!("{}{}", head, entry.value), entry.detail.clone(), entry.count, exact_start);
+                    }
+                }
+            }
+
+            // Past queries complete the whole input, not just the last word
+            let history = app_db.with(|conn| search_history::completions(conn, &prefix, limit));
+            match history {
+                Ok(queries) => {
+                    for past in queries {
+                        if past != prefix {
+                            push(SuggestionKind::History, &past, past.clone(), None, 0, true);
+                        }
+                    }
+                },
+                Err(e) => log::warn!("Failed to read search history for autocomplete: {}", e),
+            }
+        },
+    }
+
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut suggestions: Vec<Suggestion> = Vec::new();
+    for (_, suggestion) in scored {
+        if suggestions.len() == limit {
+            break;
+        }
+        if !suggestions.iter().any(|s| s.value == suggestion.value) {
+            suggestions.push(suggestion);
+        }
+    }
+    Ok(suggestions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_db;
+
+    #[test]
+    fn split_last_token_keeps_quoted_values_together() {
+        assert_eq!(split_last_token("dinner fr"), ("dinner ", "fr"));
+        assert_eq!(split_last_token("dinner "), ("dinner ", ""));
+        assert_eq!(split_last_token(r#"pizza in:"Book cl"#), ("pizza ", r#"in:"Book cl"#));
+        assert_eq!(split_last_token("from"), ("", "from"));
+    }
+
+    #[test]
+    fn lookup_finds_any_word_of_a_label() {
+        let mut index = SuggestIndex::default();
+        index.add(SuggestionKind::Contact, "Jane Doe".to_string(), "from:+15550100".to_string(), Some("+15550100".to_string()), 3);
+        index.add(SuggestionKind::Term, "dinner".to_string(), "dinner".to_string(), None, 5);
+        index.keys.sort_unstable();
+
+        let labels = |prefix: &str| index.lookup(prefix).iter().map(|e| e.label.clone()).collect::<Vec<_>>();
+        assert_eq!(labels("doe"), vec!["Jane Doe"]);
+        assert_eq!(labels("ja"), vec!["Jane Doe"]);
+        assert_eq!(labels("+1555"), vec!["Jane Doe"]);
+        assert_eq!(labels("din"), vec!["dinner"]);
+        assert!(labels("x").is_empty());
+    }
+
+    #[test]
+    fn aliases_version_ignores_search_history() {
+        let conn = app_db::open_test_db();
+        let empty = aliases_version(&conn).unwrap();
+
+        conn.execute("INSERT INTO search_history (query, params, searched_at) VALUES ('hi', '{}', 1)", []).unwrap();
+        assert_eq!(aliases_version(&conn).unwrap(), empty);
+
+        conn.execute("INSERT INTO handle_alias (handle_key, handle, display_name) VALUES ('5551234567', '5551234567', 'Ann')", [])
+            .unwrap();
+        let named = aliases_version(&conn).unwrap();
+        assert_ne!(named, empty);
+
+        conn.execute("UPDATE handle_alias SET display_name = 'Annie'", []).unwrap();
+        assert_ne!(aliases_version(&conn).unwrap(), named);
+    }
+}
@@ -0,0 +1,343 @@
+// Text folding used for case- and diacritic-insensitive search.
+//
+// SQLite's LIKE only folds ASCII, so both the query and the message text are
+// run through `fold_text` before being compared. The function is registered on
+// the chat.db connection as `fold(text)`, next to a `regexp` function that
+// backs SQLite's REGEXP operator with the Rust regex engine.
+use regex::Regex;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::Connection;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Japanese voiced sound marks are combining marks too, but stripping them would
+// turn が into か, so they are kept and recomposed below.
+fn is_kana_voicing_mark(c: char) -> bool {
+    c == '\u{3099}' || c == '\u{309A}'
+}
+
+fn push_folded_char(c: char, out: &mut String) {
+    match c {
+        // Full case folding differs from lowercasing for a handful of characters
+        'ß' | 'ẞ' => out.push_str("ss"),
+        'ς' => out.push('σ'),
+        _ => out.extend(c.to_lowercase()),
+    }
+}
+
+/// NFKD-normalizes `text`, strips diacritics and applies Unicode case folding.
+///
+/// The result is recomposed with NFC so Hangul syllables and voiced kana stay
+/// single characters, which keeps CJK substring matching intact.
+pub fn fold_text(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.nfkd() {
+        if is_combining_mark(c) && !is_kana_voicing_mark(c) {
+            continue;
+        }
+        push_folded_char(c, &mut folded);
+    }
+    folded.nfc().collect()
+}
+
+/// Registers the custom SQL functions used by search on `conn`.
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "fold",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let text: Option<String> = ctx.get(0)?;
+            Ok(text.map(|t| fold_text(&t)))
+        },
+    )?;
+
+    // `X REGEXP Y` calls regexp(Y, X). The compiled pattern is cached per statement.
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let regex = ctx.get_or_create_aux(0, |vr| -> Result<Regex, Box<dyn std::error::Error + Send + Sync>> {
+                Ok(Regex::new(vr.as_str()?)?)
+            })?;
+            let text: Option<String> = ctx.get(1)?;
+            Ok(text.map(|t| regex.is_match(&t)))
+        },
+    )
+}
+
+/// Regex matching `folded_query` as a whole word.
+///
+/// An end that is a word character must sit at a word boundary (`\b`). An end
+/// that isn't, as in "c++" or "#tag", must not (`\B`), so its neighbour is
+/// another non-word character or the edge of the text. Neither assertion
+/// consumes a character, which keeps highlights on the query itself.
+pub fn whole_word_pattern(folded_query: &str) -> String {
+    let boundary = |c: Option<char>| match c {
+        Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+        _ => r"\B",
+    };
+    format!(
+        "{}{}{}",
+        boundary(folded_query.chars().next()),
+        regex::escape(folded_query),
+        boundary(folded_query.chars().last())
+    )
+}
+
+/// Splits folded text into words on anything that is not a letter or digit.
+pub fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty())
+}
+
+/// Folded text that remembers where each folded byte came from in the original.
+///
+/// Text is folded one grapheme cluster at a time. Normalization never combines
+/// characters across clusters, so the result is the same as `fold_text` on the
+/// whole string, which is what search matched in SQL. Offsets into the original
+/// are in UTF-16 code units so the frontend can use them directly on
+/// JavaScript strings.
+pub struct FoldedText {
+    pub folded: String,
+    // UTF-16 range of the original cluster for every byte of `folded`
+    spans: Vec<(usize, usize)>,
+}
+
+impl FoldedText {
+    pub fn new(text: &str) -> Self {
+        let mut folded = String::with_capacity(text.len());
+        let mut spans = Vec::with_capacity(text.len());
+        let mut position = 0;
+
+        for cluster in text.graphemes(true) {
+            let width = cluster.encode_utf16().count();
+            let piece = fold_text(cluster);
+            spans.extend(std::iter::repeat_n((position, position + width), piece.len()));
+            folded.push_str(&piece);
+            position += width;
+        }
+
+        FoldedText { folded, spans }
+    }
+
+    // Maps a non-empty byte range of `folded` back to the original text
+    fn original_range(&self, start: usize, end: usize) -> (usize, usize) {
+        (self.spans[start].0, self.spans[end - 1].1)
+    }
+
+    pub fn find_term(&self, term: &str) -> Vec<(usize, usize)> {
+        if term.is_empty() {
+            return Vec::new();
+        }
+        self.folded
+            .match_indices(term)
+            .map(|(start, m)| self.original_range(start, start + m.len()))
+            .collect()
+    }
+
+    pub fn find_pattern(&self, regex: &Regex) -> Vec<(usize, usize)> {
+        regex
+            .find_iter(&self.folded)
+            .filter(|m| !m.is_empty())
+            .map(|m| self.original_range(m.start(), m.end()))
+            .collect()
+    }
+}
+
+fn utf16_offset(text: &str, byte_offset: usize) -> usize {
+    text[..byte_offset].encode_utf16().count()
+}
+
+/// What to highlight in each search result, derived from the query and match mode.
+pub enum Highlighter {
+    // Folded terms matched as substrings of the folded text
+    Terms(Vec<String>),
+    // Pattern matched against the folded text
+    FoldedPattern(Regex),
+    // Pattern matched against the original text
+    Pattern(Regex),
+}
+
+impl Highlighter {
+    /// Sorted, non-overlapping UTF-16 ranges of `text` that matched the query.
+    pub fn highlight(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut ranges = match self {
+            Highlighter::Terms(terms) => {
+                let folded = FoldedText::new(text);
+                terms.iter().flat_map(|term| folded.find_term(term)).collect()
+            },
+            Highlighter::FoldedPattern(regex) => FoldedText::new(text).find_pattern(regex),
+            Highlighter::Pattern(regex) => regex
+                .find_iter(text)
+                .filter(|m| !m.is_empty())
+                .map(|m| (utf16_offset(text, m.start()), utf16_offset(text, m.end())))
+                .collect::<Vec<_>>(),
+        };
+
+        ranges.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+}
+
+/// Cuts an excerpt of about `context_chars` characters either side of the UTF-16
+/// range `around`, marking trimmed ends with an ellipsis.
+pub fn snippet(text: &str, around: (usize, usize), context_chars: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+
+    // Convert the UTF-16 range to char indices
+    let mut position = 0;
+    let mut start_char = chars.len();
+    let mut end_char = chars.len();
+    for (i, c) in chars.iter().enumerate() {
+        if position >= around.0 && start_char == chars.len() {
+            start_char = i;
+        }
+        if position >= around.1 {
+            end_char = i;
+            break;
+        }
+        position += c.len_utf16();
+    }
+
+    let from = start_char.saturating_sub(context_chars);
+    let to = (end_char + context_chars).min(chars.len());
+
+    let body: String = chars[from..to].iter().collect();
+    let mut excerpt = String::new();
+    if from > 0 {
+        excerpt.push('…');
+    }
+    excerpt.push_str(body.trim());
+    if to < chars.len() {
+        excerpt.push('…');
+    }
+    excerpt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_text_ignores_case_and_diacritics() {
+        assert_eq!(fold_text("Crème BRÛLÉE"), "creme brulee");
+        assert_eq!(fold_text("Ångström"), "angstrom");
+        assert_eq!(fold_text("Straße"), "strasse");
+        assert_eq!(fold_text("ΟΔΟΣ"), fold_text("οδος"));
+    }
+
+    #[test]
+    fn fold_text_treats_composed_and_decomposed_alike() {
+        assert_eq!(fold_text("caf\u{e9}"), fold_text("cafe\u{301}"));
+        assert_eq!(fold_text("ｆｕｌｌ"), "full");
+    }
+
+    #[test]
+    fn fold_text_keeps_cjk_intact() {
+        // Voiced kana stay voiced and Hangul syllables stay composed
+        assert_eq!(fold_text("がっこう"), "がっこう");
+        assert_eq!(fold_text("か\u{3099}"), "が");
+        assert_eq!(fold_text("한국어"), "한국어");
+        assert_eq!(fold_text("中文"), "中文");
+    }
+
+    fn whole_word(query: &str, text: &str) -> bool {
+        Regex::new(&whole_word_pattern(query)).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn whole_word_pattern_matches_words() {
+        assert!(whole_word("cat", "the cat sat"));
+        assert!(whole_word("cat", "cat"));
+        assert!(!whole_word("cat", "concatenate"));
+        assert!(whole_word("new york", "in new york!"));
+    }
+
+    #[test]
+    fn whole_word_pattern_handles_symbols_at_either_end() {
+        assert!(whole_word("c++", "i like c++ a lot"));
+        assert!(whole_word("c++", "c++"));
+        assert!(!whole_word("c++", "abc++"));
+        assert!(!whole_word("c++", "c++x"));
+        assert!(whole_word("#tag", "see #tag."));
+        assert!(!whole_word("#tag", "see #tags"));
+        assert!(whole_word("?!", "?! indeed"));
+        assert!(whole_word("?!", "really ?!"));
+    }
+
+    #[test]
+    fn folded_text_matches_fold_text() {
+        for text in [
+            "Crème brûlée",
+            "cafe\u{301} au lait",
+            "か\u{3099}っこう",
+            "\u{1100}\u{1161}\u{11A8}",
+            "Straße 😀 ÅNGSTRÖM",
+            "ｆｕｌｌ ｗｉｄｔｈ",
+        ] {
+            assert_eq!(FoldedText::new(text).folded, fold_text(text), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn highlight_offsets_are_utf16_ranges_of_the_original() {
+        let terms = |t: &str| Highlighter::Terms(vec![fold_text(t)]);
+        assert_eq!(terms("CAFE").highlight("Un café noir"), vec![(3, 7)]);
+        // A decomposed é is two code units
+        assert_eq!(terms("cafe").highlight("Un cafe\u{301} noir"), vec![(3, 8)]);
+        // An emoji is a surrogate pair
+        assert_eq!(terms("café").highlight("😀 café"), vec![(3, 7)]);
+        assert_eq!(terms("strasse").highlight("Große Straße"), vec![(6, 12)]);
+    }
+
+    #[test]
+    fn highlight_offsets_on_cjk() {
+        let terms = |t: &str| Highlighter::Terms(vec![fold_text(t)]);
+        assert_eq!(terms("がっこう").highlight("今日はがっこう"), vec![(3, 7)]);
+        // Decomposed voiced kana still match and cover the voicing mark
+        assert_eq!(terms("が").highlight("まか\u{3099}"), vec![(1, 3)]);
+        assert_eq!(terms("한국").highlight("나는 한국어"), vec![(3, 5)]);
+        // Hangul written as separate jamo
+        assert_eq!(terms("각").highlight("x\u{1100}\u{1161}\u{11A8}"), vec![(1, 4)]);
+    }
+
+    #[test]
+    fn highlight_merges_overlapping_ranges() {
+        let highlighter = Highlighter::Terms(vec!["ab".to_string(), "bc".to_string()]);
+        assert_eq!(highlighter.highlight("abc abc"), vec![(0, 3), (4, 7)]);
+        let pattern = Highlighter::Pattern(Regex::new("b+").unwrap());
+        assert_eq!(pattern.highlight("😀bb"), vec![(2, 4)]);
+    }
+
+    #[test]
+    fn snippet_trims_around_the_match() {
+        let text = format!("{} café {}", "a".repeat(50), "b".repeat(50));
+        let range = Highlighter::Terms(vec!["cafe".to_string()]).highlight(&text)[0];
+        assert_eq!(snippet(&text, range, 3), "…aa café bb…");
+        assert_eq!(snippet("😀😀 café 😀😀", (5, 9), 2), "…😀 café 😀…");
+        assert_eq!(snippet("short café", (6, 10), 60), "short café");
+    }
+
+    #[test]
+    fn folded_function_matches_in_sql() {
+        let conn = Connection::open_in_memory().unwrap();
+        register_functions(&conn).unwrap();
+        let found: bool = conn
+            .query_row("SELECT instr(fold('Le Café Noir'), fold('CAFE')) > 0", [], |row| row.get(0))
+            .unwrap();
+        assert!(found);
+        let matched: bool = conn
+            .query_row("SELECT 'order 66' REGEXP '[0-9]+'", [], |row| row.get(0))
+            .unwrap();
+        assert!(matched);
+    }
+}
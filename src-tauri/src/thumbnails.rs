@@ -0,0 +1,425 @@
+// Thumbnails of image attachments, cached on disk and served over thumbnail://.
+//
+// thumbnail://localhost/<attachment guid>?size=256 returns a thumbnail no
+// larger than the size in either dimension, rounded up to one of SIZES so
+// the cache stays small. JPEG, PNG and GIF are decoded by the image crate and
+// HEIC by libheif, which is built into the app. Thumbnails are keyed by guid,
+// source mtime and size, so an edited or re-downloaded file gets a new one,
+// and the least recently used are evicted past the cache limit. Requests are
+// handled by a few worker threads, so a gallery page queues its decodes
+// instead of running them all at once.
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, RgbImage, RgbaImage};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::UriSchemeResponder;
+
+use crate::{app_db, attachments, AppError};
+
+pub const SCHEME: &str = "thumbnail";
+
+// Thumbnail sizes in pixels; requests are rounded up to the next one
+const SIZES: &[u32] = &[64, 128, 256, 512, 1024];
+pub const DEFAULT_SIZE: u32 = 256;
+
+const CACHE_LIMIT_SETTING: &str = "thumbnail_cache_limit_mb";
+const DEFAULT_CACHE_LIMIT_MB: u64 = 200;
+// Eviction frees space down to this share of the limit, so it doesn't run on every write
+const EVICT_TO_PERCENT: u64 = 80;
+
+// Most thumbnails generated at once
+const MAX_WORKERS: usize = 4;
+
+#[derive(Serialize, Debug)]
+pub struct ThumbnailCacheInfo {
+    path: String,
+    files: usize,
+    bytes: u64,
+    limit_bytes: u64,
+}
+
+fn cache_dir() -> Result<PathBuf, AppError> {
+    let dir = dirs::cache_dir()
+        .ok_or(AppError::OtherError("Cache directory not found".to_string()))?
+        .join("iMessage Search")
+        .join("thumbnails");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn cache_limit_bytes() -> u64 {
+    let limit_mb = app_db::open_app_db()
+        .and_then(|conn| app_db::get_setting(&conn, CACHE_LIMIT_SETTING))
+        .ok()
+        .flatten()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_LIMIT_MB);
+    limit_mb * 1024 * 1024
+}
+
+fn bucket(size: u32) -> u32 {
+    SIZES.iter().copied().find(|s| *s >= size).unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// URL of the thumbnail of an attachment.
+pub fn thumbnail_url(attachment_guid: &str, size: u32) -> String {
+    format!("{}://localhost/{}?size={}", SCHEME, attachment_guid, bucket(size))
+}
+
+fn is_heic(path: &Path, mime_type: Option<&str>) -> bool {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    matches!(extension.as_str(), "heic" | "heif") || matches!(mime_type, Some("image/heic") | Some("image/heif"))
+}
+
+// Cache file for a source, without extension; thumbnails are .jpg, or .png with alpha
+fn cache_stem(dir: &Path, guid: &str, mtime: u64, size: u32) -> PathBuf {
+    let guid: String = guid.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-').collect();
+    dir.join(format!("{}-{}-{}", guid, mtime, size))
+}
+
+fn cached(stem: &Path) -> Option<PathBuf> {
+    ["jpg", "png"].iter().map(|ext| stem.with_extension(ext)).find(|path| path.is_file())
+}
+
+// Writes the thumbnail to a temporary file and renames it into place, so
+// concurrent requests never see a partly written thumbnail
+fn encode(image: DynamicImage, stem: &Path) -> Result<PathBuf, AppError> {
+    static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let (path, image, format) = if image.color().has_alpha() {
+        (stem.with_extension("png"), image, ImageFormat::Png)
+    } else {
+        (stem.with_extension("jpg"), DynamicImage::ImageRgb8(image.to_rgb8()), ImageFormat::Jpeg)
+    };
+    let temp = stem.with_extension(format!("{}-{}.tmp", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let written = std::fs::File::create(&temp).map_err(AppError::from).and_then(|file| {
+        image
+            .write_to(&mut BufWriter::new(file), format)
+            .map_err(|e| AppError::OtherError(format!("Could not write thumbnail: {}", e)))
+    });
+    if let Err(e) = written.and_then(|_| Ok(std::fs::rename(&temp, &path)?)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+// Decodes a HEIC or HEIF file with libheif, applying its rotation and crop
+fn decode_heif(source: &Path) -> Result<DynamicImage, AppError> {
+    let error = |e: libheif_rs::HeifError| AppError::OtherError(format!("Could not decode {}: {}", source.display(), e));
+    let name = source
+        .to_str()
+        .ok_or_else(|| AppError::OtherError(format!("Unsupported path: {}", source.display())))?;
+    let context = HeifContext::read_from_file(name).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = if has_alpha { RgbChroma::Rgba } else { RgbChroma::Rgb };
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(chroma), None).map_err(error)?;
+
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or_else(|| AppError::OtherError(format!("No pixels in {}", source.display())))?;
+    // Rows may be padded past their pixels
+    let row_bytes = plane.width as usize * if has_alpha { 4 } else { 3 };
+    let pixels: Vec<u8> = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|row| &row[..row_bytes.min(row.len())])
+        .copied()
+        .collect();
+    let decoded = if has_alpha {
+        RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8)
+    };
+    decoded.ok_or_else(|| AppError::OtherError(format!("Could not decode {}", source.display())))
+}
+
+// Decodes a JPEG, PNG or GIF file, turned upright by its EXIF orientation
+fn decode_rotated(source: &Path) -> Result<DynamicImage, image::ImageError> {
+    let mut decoder = ImageReader::open(source)?.with_guessed_format()?.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+fn generate(source: &Path, mime_type: Option<&str>, stem: &Path, size: u32) -> Result<PathBuf, AppError> {
+    let image = if is_heic(source, mime_type) {
+        decode_heif(source)?
+    } else {
+        decode_rotated(source)
+            .map_err(|e| AppError::OtherError(format!("Could not decode {}: {}", source.display(), e)))?
+    };
+    encode(image.thumbnail(size, size), stem)
+}
+
+// Finished thumbnails with their modification time and size. Temporary files
+// still being written by a worker are left out.
+fn cached_files(dir: &Path) -> Result<Vec<(SystemTime, u64, PathBuf)>, AppError> {
+    Ok(std::fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_none_or(|extension| extension != "tmp"))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            metadata.is_file().then(|| (metadata.modified().unwrap_or(UNIX_EPOCH), metadata.len(), entry.path()))
+        })
+        .collect())
+}
+
+// Removes least recently used thumbnails once the cache is over its limit
+fn evict(dir: &Path, limit: u64) -> Result<(), AppError> {
+    let mut files = cached_files(dir)?;
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    if total <= limit {
+        return Ok(());
+    }
+
+    files.sort_by_key(|(modified, _, _)| *modified);
+    let target = limit * EVICT_TO_PERCENT / 100;
+    for (_, len, path) in files {
+        if total <= target {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+    log::info!("Evicted thumbnails, cache now {} bytes", total);
+    Ok(())
+}
+
+/// Path of the thumbnail of an attachment, generating it if it isn't cached.
+pub fn thumbnail(attachment_guid: &str, size: u32) -> Result<PathBuf, AppError> {
+    let size = bucket(size);
+    let conn = crate::open_imessage_db()?;
+    let (filename, mime_type): (Option<String>, Option<String>) = conn
+        .query_row(
+            "SELECT filename, mime_type FROM attachment WHERE guid = ?",
+            [attachment_guid],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+        .ok_or_else(|| AppError::OtherError(format!("Attachment {} not found", attachment_guid)))?;
+    let filename = filename.ok_or_else(|| AppError::OtherError(format!("Attachment {} has no file", attachment_guid)))?;
+
+    let resolved = attachments::Resolver::load().resolve(&filename);
+    let source = match resolved.path {
+        Some(path) if resolved.exists => PathBuf::from(path),
+        _ => return Err(AppError::OtherError(format!("Attachment {} is not on disk", attachment_guid))),
+    };
+    let mtime = std::fs::metadata(&source)?
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+
+    let dir = cache_dir()?;
+    let stem = cache_stem(&dir, attachment_guid, mtime, size);
+    if let Some(path) = cached(&stem) {
+        // Mark as recently used for eviction
+        if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        return Ok(path);
+    }
+
+    let path = generate(&source, mime_type.as_deref(), &stem, size)?;
+    if let Err(e) = evict(&dir, cache_limit_bytes()) {
+        log::warn!("Failed to evict thumbnails: {}", e);
+    }
+    Ok(path)
+}
+
+fn error_response(status: StatusCode) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(status.canonical_reason().unwrap_or("").as_bytes().to_vec())
+        .unwrap_or_default()
+}
+
+fn respond(request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let guid = request.uri().path().trim_start_matches('/');
+    if guid.is_empty() {
+        return error_response(StatusCode::NOT_FOUND);
+    }
+    let size = request
+        .uri()
+        .query()
+        .and_then(|query| url::form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == "size"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(DEFAULT_SIZE);
+
+    match thumbnail(guid, size).and_then(|path| Ok((std::fs::read(&path)?, path))) {
+        Ok((data, path)) => {
+            let content_type = if path.extension().is_some_and(|e| e == "png") { "image/png" } else { "image/jpeg" };
+            Response::builder()
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CACHE_CONTROL, "max-age=86400")
+                .body(data)
+                .unwrap_or_default()
+        },
+        Err(e) => {
+            log::warn!("No thumbnail for {}: {}", guid, e);
+            error_response(StatusCode::NOT_FOUND)
+        },
+    }
+}
+
+type Job = (Request<Vec<u8>>, UriSchemeResponder);
+
+// Queue of the worker threads, started on first use
+fn workers() -> &'static Mutex<mpsc::Sender<Job>> {
+    static QUEUE: OnceLock<Mutex<mpsc::Sender<Job>>> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let count = std::thread::available_parallelism().map_or(2, |n| n.get()).min(MAX_WORKERS);
+        for _ in 0..count {
+            let receiver = receiver.clone();
+            std::thread::spawn(move || loop {
+                let job = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                match job {
+                    Ok((request, responder)) => responder.respond(respond(&request)),
+                    Err(_) => break,
+                }
+            });
+        }
+        Mutex::new(sender)
+    })
+}
+
+/// Handles thumbnail:// requests on the worker threads, since decoding is slow.
+pub fn serve(request: Request<Vec<u8>>, responder: UriSchemeResponder) {
+    let sent = workers().lock().unwrap_or_else(|e| e.into_inner()).send((request, responder));
+    if let Err(mpsc::SendError((_, responder))) = sent {
+        responder.respond(error_response(StatusCode::SERVICE_UNAVAILABLE));
+    }
+}
+
+#[tauri::command]
+pub async fn get_thumbnail_cache_info() -> Result<ThumbnailCacheInfo, AppError> {
+    let dir = cache_dir()?;
+    let sizes: Vec<u64> = cached_files(&dir)?.into_iter().map(|(_, len, _)| len).collect();
+    Ok(ThumbnailCacheInfo {
+        path: dir.to_string_lossy().to_string(),
+        files: sizes.len(),
+        bytes: sizes.iter().sum(),
+        limit_bytes: cache_limit_bytes(),
+    })
+}
+
+/// Sets the cache size limit in megabytes and evicts down to it.
+#[tauri::command]
+pub async fn set_thumbnail_cache_limit(limit_mb: u64) -> Result<(), AppError> {
+    if limit_mb == 0 {
+        return Err(AppError::OtherError("Cache limit must be at least 1 MB".to_string()));
+    }
+    let conn = app_db::open_app_db()?;
+    app_db::set_setting(&conn, CACHE_LIMIT_SETTING, &limit_mb.to_string())?;
+    evict(&cache_dir()?, limit_mb * 1024 * 1024)
+}
+
+#[tauri::command]
+pub async fn clear_thumbnail_cache() -> Result<(), AppError> {
+    let dir = cache_dir()?;
+    std::fs::remove_dir_all(&dir)?;
+    std::fs::create_dir_all(&dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_round_up_to_a_bucket() {
+        assert_eq!(bucket(0), 64);
+        assert_eq!(bucket(64), 64);
+        assert_eq!(bucket(65), 128);
+        assert_eq!(bucket(300), 512);
+        assert_eq!(bucket(1024), 1024);
+        assert_eq!(bucket(5000), 1024);
+        assert_eq!(thumbnail_url("ABC-123", 200), "thumbnail://localhost/ABC-123?size=256");
+    }
+
+    #[test]
+    fn detects_heic_by_extension_or_mime_type() {
+        assert!(is_heic(Path::new("IMG_0001.HEIC"), None));
+        assert!(is_heic(Path::new("photo.heif"), Some("image/jpeg")));
+        assert!(is_heic(Path::new("photo"), Some("image/heic")));
+        assert!(!is_heic(Path::new("photo.jpg"), Some("image/jpeg")));
+    }
+
+    #[test]
+    fn cache_stems_keep_only_safe_characters() {
+        let stem = cache_stem(Path::new("/cache"), "../AB-12/c d", 42, 256);
+        assert_eq!(stem, Path::new("/cache/AB-12cd-42-256"));
+    }
+
+    #[test]
+    fn encode_renames_into_place() {
+        let dir = std::env::temp_dir().join(format!("thumbnails-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let opaque = encode(DynamicImage::new_rgb8(4, 4), &dir.join("opaque")).unwrap();
+        assert_eq!(opaque, dir.join("opaque.jpg"));
+        let transparent = encode(DynamicImage::new_rgba8(4, 4), &dir.join("transparent")).unwrap();
+        assert_eq!(transparent, dir.join("transparent.png"));
+        assert_eq!(cached(&dir.join("transparent")), Some(transparent));
+
+        let mut names: Vec<String> =
+            std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        assert_eq!(names, ["opaque.jpg", "transparent.png"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jpegs_are_turned_upright() {
+        let dir = std::env::temp_dir().join(format!("thumbnails-orientation-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut jpeg = Vec::new();
+        DynamicImage::new_rgb8(4, 2).write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg).unwrap();
+
+        // APP1 segment whose EXIF says to rotate 90° clockwise
+        let exif: &[u8] = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+        let mut rotated = jpeg[..2].to_vec();
+        rotated.extend_from_slice(&[0xff, 0xe1]);
+        rotated.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        rotated.extend_from_slice(exif);
+        rotated.extend_from_slice(&jpeg[2..]);
+        let path = dir.join("portrait.jpg");
+        std::fs::write(&path, rotated).unwrap();
+
+        let image = decode_rotated(&path).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 4));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn temporary_files_are_not_cached_files() {
+        let dir = std::env::temp_dir().join(format!("thumbnails-evict-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("old-1-64.jpg"), [0; 100]).unwrap();
+        std::fs::write(dir.join("new-1-64.123-0.tmp"), [0; 100]).unwrap();
+
+        assert_eq!(cached_files(&dir).unwrap().len(), 1);
+        evict(&dir, 50).unwrap();
+        assert!(!dir.join("old-1-64.jpg").exists());
+        assert!(dir.join("new-1-64.123-0.tmp").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
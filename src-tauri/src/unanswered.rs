@@ -0,0 +1,294 @@
+// Incoming messages the user never replied to.
+//
+// Messages in each chat are walked in date order. Incoming messages wait until
+// the next message from the user; if that reply comes later than the reply
+// window, or hasn't come and the window has already passed, the waiting
+// messages are reported as one unanswered thread. Any message from the user
+// counts as a reply, including tapbacks, while incoming tapbacks are never
+// treated as needing one.
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::{handle_key, AppError, Message};
+
+// Apple timestamps in chat.db are nanoseconds
+const NANOS_PER_HOUR: i64 = 3_600_000_000_000;
+
+const DEFAULT_LOOKBACK_DAYS: i64 = 30;
+const DEFAULT_LIMIT: usize = 200;
+
+// Priority added per reason
+const QUESTION_PRIORITY: u32 = 2;
+const MENTION_PRIORITY: u32 = 3;
+
+fn default_reply_window_hours() -> u32 {
+    24
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnansweredParams {
+    // A reply later than this doesn't count
+    #[serde(default = "default_reply_window_hours")]
+    reply_window_hours: u32,
+    #[serde(default)]
+    include_group_chats: bool,
+    #[serde(default)]
+    since: Option<String>,       // yyyy-MM-dd, defaults to 30 days ago
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UnansweredThread {
+    chat_id: String,
+    conversation_name: Option<String>,
+    is_group: bool,
+    // Unanswered messages, oldest first
+    messages: Vec<Message>,
+    has_question: bool,
+    mentions_me: bool,
+    priority: u32,
+}
+
+// Incoming messages waiting for a reply in one chat
+struct Pending {
+    chat_id: i64,
+    is_group: bool,
+    message_ids: Vec<i64>,
+}
+
+// Finds runs of incoming messages without a timely reply as of `now`
+fn find_pending(
+    conn: &Connection,
+    since: i64,
+    now: i64,
+    window: i64,
+    include_group_chats: bool,
+) -> Result<Vec<Pending>, AppError> {
+    let sql = r#"
+        SELECT
+            cmj.chat_id,
+            m.ROWID,
+            m.date,
+            m.is_from_me,
+            COALESCE(m.associated_message_type, 0) as associated_type,
+            (
+                SELECT COUNT(DISTINCT chj.handle_id)
+                FROM chat_handle_join chj
+                WHERE chj.chat_id = cmj.chat_id
+            ) > 1 as is_group
+        FROM
+            message m
+        INNER JOIN
+            chat_message_join cmj ON m.ROWID = cmj.message_id
+        WHERE
+            m.date > ?
+        ORDER BY
+            cmj.chat_id, m.date, m.ROWID
+    "#;
+
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows = stmt.query([since])?;
+
+    let mut unanswered: Vec<Pending> = Vec::new();
+    let mut current: Option<(Pending, i64)> = None;
+
+    // Reports the waiting run, if any, unless `reply_date` answered it in time.
+    // A run with no reply yet is only reported once its window has passed.
+    let mut settle = |waiting: Option<(Pending, i64)>, reply_date: Option<i64>| {
+        if let Some((pending, first_date)) = waiting {
+            let overdue = reply_date.unwrap_or(now) - first_date > window;
+            if overdue {
+                unanswered.push(pending);
+            }
+        }
+    };
+
+    while let Some(row) = rows.next()? {
+        let chat_id: i64 = row.get(0)?;
+        let message_id: i64 = row.get(1)?;
+        let date: i64 = row.get(2)?;
+        let is_from_me: bool = row.get::<_, i64>(3)? == 1;
+        let associated_type: i64 = row.get(4)?;
+        let is_group: bool = row.get(5)?;
+
+        if current.as_ref().is_some_and(|(p, _)| p.chat_id != chat_id) {
+            settle(current.take(), None);
+        }
+        if is_group && !include_group_chats {
+            continue;
+        }
+
+        if is_from_me {
+            settle(current.take(), Some(date));
+        } else if associated_type == 0 {
+            match &mut current {
+                Some((pending, _)) => pending.message_ids.push(message_id),
+                None => {
+                    current = Some((Pending { chat_id, is_group, message_ids: vec![message_id] }, date));
+                },
+            }
+        }
+    }
+    settle(current.take(), None);
+
+    Ok(unanswered)
+}
+
+fn load_messages(conn: &Connection, chat_id: i64, message_ids: &[i64]) -> Result<Vec<Message>, AppError> {
+    let ids: Vec<String> = message_ids.iter().map(|id| id.to_string()).collect();
+    let sql = format!(r#"
+        SELECT
+            {}, c.display_name as conversation_name
+        FROM
+            message m
+        INNER JOIN
+            chat_message_join cmj ON m.ROWID = cmj.message_id
+        INNER JOIN
+            chat c ON cmj.chat_id = c.ROWID
+        LEFT JOIN
+            handle h ON m.handle_id = h.ROWID
+        WHERE
+            cmj.chat_id = ? AND m.ROWID IN ({})
+        ORDER BY
+            m.date, m.ROWID
+    "#, crate::message_columns(), ids.join(", "));
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut messages: Vec<Message> = stmt
+        .query_map([chat_id], crate::message_from_row)?
+        .filter_map(Result::ok)
+        .collect();
+    crate::annotate_messages(&mut messages);
+    Ok(messages)
+}
+
+/// Lists threads of incoming messages with no reply from the user within the
+/// reply window, highest priority first.
+#[tauri::command]
+pub async fn find_unanswered(params: UnansweredParams) -> Result<Vec<UnansweredThread>, AppError> {
+    log::info!("Finding unanswered messages: {:?}", params);
+    let conn = crate::open_imessage_db()?;
+
+    let since = match &params.since {
+        Some(date) => crate::date_to_apple_timestamp(date)
+            .ok_or_else(|| AppError::OtherError(format!("Invalid date: {}", date)))?,
+        None => {
+            let start = chrono::Local::now().date_naive() - chrono::Duration::days(DEFAULT_LOOKBACK_DAYS);
+            crate::date_to_apple_timestamp(&start.format("%Y-%m-%d").to_string()).unwrap_or(0)
+        },
+    };
+    let window = params.reply_window_hours as i64 * NANOS_PER_HOUR;
+    let now = (chrono::Utc::now().timestamp() - 978307200) * 1_000_000_000;
+
+    let my_handles: HashSet<String> = crate::my_handles(&conn)?.iter().map(|h| handle_key(h)).collect();
+
+    let mut threads = Vec::new();
+    for pending in find_pending(&conn, since, now, window, params.include_group_chats)? {
+        let messages = load_messages(&conn, pending.chat_id, &pending.message_ids)?;
+        if messages.is_empty() {
+            continue;
+        }
+
+        let has_question = messages.iter().any(|m| m.text.contains('?'));
+        let mentions_me = messages
+            .iter()
+            .flat_map(|m| &m.mentioned_handles)
+            .any(|handle| my_handles.contains(&handle_key(handle)));
+
+        let mut priority = 1;
+        if has_question {
+            priority += QUESTION_PRIORITY;
+        }
+        if mentions_me {
+            priority += MENTION_PRIORITY;
+        }
+
+        threads.push(UnansweredThread {
+            chat_id: pending.chat_id.to_string(),
+            conversation_name: messages[0].conversation_name.clone(),
+            is_group: pending.is_group,
+            messages,
+            has_question,
+            mentions_me,
+            priority,
+        });
+    }
+
+    // Highest priority first, most recent first within a priority
+    threads.sort_by(|a, b| {
+        let latest = |t: &UnansweredThread| t.messages.last().map(|m| m.date).unwrap_or(0);
+        b.priority.cmp(&a.priority).then(latest(b).cmp(&latest(a)))
+    });
+    threads.truncate(params.limit.unwrap_or(DEFAULT_LIMIT));
+
+    log::info!("Found {} unanswered threads", threads.len());
+    Ok(threads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = NANOS_PER_HOUR;
+
+    // chat 1 is one-to-one, chat 2 is a group
+    fn test_db(messages: &[(i64, i64, i64, bool, i64)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE message (ROWID INTEGER PRIMARY KEY, date INTEGER, is_from_me INTEGER, associated_message_type INTEGER);
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+            CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER);
+            INSERT INTO chat_handle_join VALUES (1, 1), (2, 1), (2, 2);
+            "#,
+        )
+        .unwrap();
+        for &(id, chat_id, date, is_from_me, associated_type) in messages {
+            conn.execute(
+                "INSERT INTO message VALUES (?, ?, ?, ?)",
+                rusqlite::params![id, date, is_from_me, associated_type],
+            )
+            .unwrap();
+            conn.execute("INSERT INTO chat_message_join VALUES (?, ?)", [chat_id, id]).unwrap();
+        }
+        conn
+    }
+
+    fn pending_ids(conn: &Connection, now: i64, include_group_chats: bool) -> Vec<Vec<i64>> {
+        find_pending(conn, 0, now, 24 * HOUR, include_group_chats)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.message_ids)
+            .collect()
+    }
+
+    #[test]
+    fn recent_messages_are_not_yet_unanswered() {
+        let conn = test_db(&[(1, 1, 10 * HOUR, false, 0), (2, 1, 11 * HOUR, false, 0)]);
+        assert!(pending_ids(&conn, 12 * HOUR, false).is_empty());
+        assert_eq!(pending_ids(&conn, 40 * HOUR, false), vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn late_replies_do_not_count() {
+        let conn = test_db(&[
+            (1, 1, HOUR, false, 0),
+            (2, 1, 2 * HOUR, true, 0),
+            (3, 1, 3 * HOUR, false, 0),
+            (4, 1, 30 * HOUR, true, 0),
+        ]);
+        assert_eq!(pending_ids(&conn, 100 * HOUR, false), vec![vec![3]]);
+    }
+
+    #[test]
+    fn incoming_tapbacks_and_group_chats() {
+        let conn = test_db(&[
+            (1, 1, HOUR, false, 2000),
+            (2, 2, HOUR, false, 0),
+        ]);
+        assert!(pending_ids(&conn, 100 * HOUR, false).is_empty());
+        assert_eq!(pending_ids(&conn, 100 * HOUR, true), vec![vec![2]]);
+    }
+}
@@ -0,0 +1,269 @@
+// Word vocabulary built from the user's own messages.
+//
+// Every distinct folded word is indexed by its character trigrams, which lets
+// fuzzy search and "did you mean" suggestions find words within a small edit
+// distance without scanning the whole vocabulary. The index is kept in memory
+// and extended with new messages on each use.
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::text;
+
+// Words outside this length range are not worth correcting
+const MIN_WORD_CHARS: usize = 3;
+const MAX_WORD_CHARS: usize = 32;
+
+// Standard BM25 tuning
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+#[derive(Default)]
+pub struct Vocabulary {
+    words: Vec<String>,
+    // Total occurrences of each word
+    counts: Vec<u32>,
+    // Number of messages containing each word
+    doc_counts: Vec<u32>,
+    ids: HashMap<String, u32>,
+    trigrams: HashMap<String, Vec<u32>>,
+    total_docs: u64,
+    total_words: u64,
+    max_rowid: i64,
+}
+
+// Managed Tauri state holding the vocabulary between commands
+#[derive(Default)]
+pub struct VocabularyState(pub Mutex<Vocabulary>);
+
+// A vocabulary word close to a query word
+#[derive(Debug, Clone)]
+pub struct Correction {
+    pub word: String,
+    pub distance: usize,
+    pub count: u32,
+}
+
+fn trigrams(word: &str) -> Vec<String> {
+    let padded: Vec<char> = std::iter::once('$')
+        .chain(word.chars())
+        .chain(std::iter::once('$'))
+        .collect();
+    let mut grams: Vec<String> = padded.windows(3).map(|w| w.iter().collect()).collect();
+    grams.sort();
+    grams.dedup();
+    grams
+}
+
+// Edits allowed before two words stop being considered the same
+pub fn max_distance(word: &str) -> usize {
+    if word.chars().count() <= 4 {
+        1
+    } else {
+        2
+    }
+}
+
+fn is_indexable(word: &str) -> bool {
+    let len = word.chars().count();
+    (MIN_WORD_CHARS..=MAX_WORD_CHARS).contains(&len) && !word.chars().all(|c| c.is_numeric())
+}
+
+impl Vocabulary {
+    fn add_word(&mut self, word: &str) -> u32 {
+        if let Some(&id) = self.ids.get(word) {
+            self.counts[id as usize] += 1;
+            return id;
+        }
+
+        let id = self.words.len() as u32;
+        for gram in trigrams(word) {
+            self.trigrams.entry(gram).or_default().push(id);
+        }
+        self.words.push(word.to_string());
+        self.counts.push(1);
+        self.doc_counts.push(0);
+        self.ids.insert(word.to_string(), id);
+        id
+    }
+
+    /// Indexes any messages added to chat.db since the last update.
+    pub fn update(&mut self, conn: &Connection) -> rusqlite::Result<()> {
+        let mut stmt = conn.prepare(
+            "SELECT ROWID, text FROM message WHERE ROWID > ? AND text IS NOT NULL ORDER BY ROWID",
+        )?;
+        let mut rows = stmt.query([self.max_rowid])?;
+
+        let mut indexed = 0;
+        while let Some(row) = rows.next()? {
+            let rowid: i64 = row.get(0)?;
+            let message_text: String = row.get(1)?;
+            let folded = text::fold_text(&message_text);
+            let mut seen = Vec::new();
+            for word in text::words(&folded) {
+                self.total_words += 1;
+                if is_indexable(word) {
+                    seen.push(self.add_word(word));
+                }
+            }
+            seen.sort_unstable();
+            seen.dedup();
+            for id in seen {
+                self.doc_counts[id as usize] += 1;
+            }
+            self.total_docs += 1;
+            self.max_rowid = rowid;
+            indexed += 1;
+        }
+
+        if indexed > 0 {
+            log::info!("Indexed {} messages into vocabulary ({} words)", indexed, self.words.len());
+        }
+        Ok(())
+    }
+
+    /// Number of times `word` (already folded) appears across all messages.
+    pub fn count(&self, word: &str) -> u32 {
+        self.ids.get(word).map(|&id| self.counts[id as usize]).unwrap_or(0)
+    }
+
+    /// Words seen at least `min_count` times, with their counts.
+    pub fn frequent_words(&self, min_count: u32) -> impl Iterator<Item = (&str, u32)> {
+        self.words
+            .iter()
+            .zip(&self.counts)
+            .filter(move |(_, &count)| count >= min_count)
+            .map(|(word, &count)| (word.as_str(), count))
+    }
+
+    /// BM25 relevance of a message for the given query terms.
+    ///
+    /// `terms` holds, for each query word, the variants accepted for it with
+    /// their edit distance; variants further from the query count for less.
+    /// Term frequency counts substring occurrences, matching how search filters.
+    pub fn bm25(&self, folded_text: &str, terms: &[Vec<(String, usize)>]) -> f64 {
+        let total_docs = self.total_docs.max(1) as f64;
+        let average_length = (self.total_words as f64 / total_docs).max(1.0);
+        let length = text::words(folded_text).count() as f64;
+        let length_norm = 1.0 - BM25_B + BM25_B * length / average_length;
+
+        let mut score = 0.0;
+        for variants in terms {
+            for (term, distance) in variants {
+                let frequency = folded_text.matches(term.as_str()).count() as f64;
+                if frequency == 0.0 {
+                    continue;
+                }
+                let doc_count = self
+                    .ids
+                    .get(term)
+                    .map(|&id| self.doc_counts[id as usize] as f64)
+                    .unwrap_or(0.0);
+                let idf = ((total_docs - doc_count + 0.5) / (doc_count + 0.5) + 1.0).ln();
+                let weight = 1.0 / (1 + distance) as f64;
+                score += weight * idf * frequency * (BM25_K1 + 1.0) / (frequency + BM25_K1 * length_norm);
+            }
+        }
+        score
+    }
+
+    /// Vocabulary words within `max_distance(word)` edits of `word`, closest and
+    /// most frequent first. The word itself is included when it occurs.
+    pub fn corrections(&self, word: &str, limit: usize) -> Vec<Correction> {
+        let max = max_distance(word);
+        let word_len = word.chars().count();
+        let grams = trigrams(word);
+
+        // Count shared trigrams per candidate so only plausible words get a full distance check
+        let mut shared: HashMap<u32, usize> = HashMap::new();
+        for gram in &grams {
+            if let Some(ids) = self.trigrams.get(gram) {
+                for &id in ids {
+                    *shared.entry(id).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // Each edit can destroy at most three trigrams
+        let min_shared = grams.len().saturating_sub(3 * max).max(1);
+
+        let mut corrections: Vec<Correction> = shared
+            .into_iter()
+            .filter(|&(_, n)| n >= min_shared)
+            .filter_map(|(id, _)| {
+                let candidate = &self.words[id as usize];
+                if candidate.chars().count().abs_diff(word_len) > max {
+                    return None;
+                }
+                let distance = strsim::levenshtein(word, candidate);
+                if distance > max {
+                    return None;
+                }
+                Some(Correction {
+                    word: candidate.clone(),
+                    distance,
+                    count: self.counts[id as usize],
+                })
+            })
+            .collect();
+
+        corrections.sort_by(|a, b| {
+            a.distance
+                .cmp(&b.distance)
+                .then(b.count.cmp(&a.count))
+                .then(a.word.cmp(&b.word))
+        });
+        corrections.truncate(limit);
+        corrections
+    }
+
+    /// Builds corrected versions of `folded_query` for "did you mean" prompts.
+    ///
+    /// Words that are already common in the user's messages are kept as typed;
+    /// the rest are replaced by more frequent words within edit distance.
+    pub fn suggest_queries(&self, folded_query: &str, limit: usize) -> Vec<String> {
+        let words: Vec<&str> = text::words(folded_query).collect();
+        let alternatives: Vec<Vec<String>> = words
+            .iter()
+            .map(|&word| {
+                let own_count = self.count(word);
+                self.corrections(word, limit + 1)
+                    .into_iter()
+                    .filter(|c| c.word != word && c.count > own_count)
+                    .map(|c| c.word)
+                    .collect()
+            })
+            .collect();
+
+        if alternatives.iter().all(|alts| alts.is_empty()) {
+            return Vec::new();
+        }
+
+        // Every suggestion uses the best correction for each word, except that
+        // later suggestions swap in the runner-up corrections one at a time
+        let best: Vec<&str> = words
+            .iter()
+            .zip(&alternatives)
+            .map(|(&word, alts)| alts.first().map(String::as_str).unwrap_or(word))
+            .collect();
+
+        let mut suggestions = Vec::new();
+        let mut push = |candidate: String| {
+            if candidate != folded_query && !suggestions.contains(&candidate) {
+                suggestions.push(candidate);
+            }
+        };
+
+        push(best.join(" "));
+        for (i, alts) in alternatives.iter().enumerate() {
+            for alt in alts.iter().skip(1) {
+                let mut variant = best.clone();
+                variant[i] = alt;
+                push(variant.join(" "));
+            }
+        }
+
+        suggestions.truncate(limit);
+        suggestions
+    }
+}
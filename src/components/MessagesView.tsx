@@ -6,8 +6,7 @@ import { ScrollArea } from "@/components/ui/scroll-area"
 import { Skeleton } from "@/components/ui/skeleton"
 import { cn } from "@/lib/utils"
 import { Contact, Message } from "@/types"
-import { convertFileSrc, invoke } from "@tauri-apps/api/core"
-import { homeDir } from "@tauri-apps/api/path"
+import { invoke } from "@tauri-apps/api/core"
 import { openUrl } from "@tauri-apps/plugin-opener"
 import { useEffect, useState } from "react"
 
@@ -250,13 +249,13 @@ const MessageText = ({ text }: { text: string }) => {
 // Modify the AttachmentView component
 const AttachmentView = ({
 	path,
+	url,
 	mimeType,
-	getAssetUrl,
 	messageText,
 }: {
 	path: string
+	url?: string | null
 	mimeType?: string
-	getAssetUrl: (path: string) => string
 	messageText?: string
 }) => {
 	// If this is an OpenGraph preview attachment and we have a URL in the message text,
@@ -266,7 +265,10 @@ const AttachmentView = ({
 		return <LinkPreview url={url} />
 	}
 
-	const url = getAssetUrl(path)
+	// Only files below the attachments folder are served to the webview
+	if (!url) {
+		return <span>Attachment unavailable</span>
+	}
 
 	// Handle images
 	if (mimeType?.startsWith("image/")) {
@@ -339,14 +341,8 @@ export function MessagesView({
 	messages,
 	conversations,
 }: MessagesViewProps) {
-	const [homePath, setHomePath] = useState<string>("")
 	const hasSearched = messages !== null
 
-	useEffect(() => {
-		// Get the home directory path when component mounts
-		homeDir().then(setHomePath).catch(console.error)
-	}, [])
-
 	// Add a function to get conversation name
 	const getConversationName = (chatId: string | undefined) => {
 		if (!chatId) return "Unknown Conversation"
@@ -382,16 +378,6 @@ export function MessagesView({
 		)
 	}
 
-	const getAssetUrl = (filePath: string) => {
-		// expand ~
-		const abs = filePath.startsWith("~")
-			? filePath.replace("~", homePath)
-			: filePath
-
-		// tauri-safe url (asset:// or 127.0.0.1 blob behind the scenes)
-		return convertFileSrc(abs)
-	}
-
 	return (
 		<div className='flex flex-col overflow-y-auto'>
 			<ScrollArea className='flex-1'>
@@ -461,8 +447,8 @@ export function MessagesView({
 										<div className='text-xs text-muted-foreground mt-2'>
 											<AttachmentView
 												path={message.attachment_path}
+												url={message.attachment_url}
 												mimeType={message.attachment_mime_type}
-												getAssetUrl={getAssetUrl}
 												messageText={message.text}
 											/>
 										</div>
@@ -19,6 +19,8 @@ export type Message = {
 	sender_name?: string
 	contact?: Contact
 	attachment_path?: string
+	// attachment:// URL, set for files below the attachments folder
+	attachment_url?: string | null
 	attachment_mime_type?: string
 	conversation_name: string
 }